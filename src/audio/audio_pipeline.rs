@@ -1,112 +1,443 @@
-use std::sync::{Arc, atomic::{AtomicBool, Ordering}};
+use std::{collections::VecDeque, pin::Pin, sync::{Arc, atomic::{AtomicBool, AtomicU64, Ordering}}, time::Duration};
 use cpal::{Stream};
-use tokio::{sync::{mpsc::{self, Receiver}}, task::JoinHandle};
-use tracing::{debug, error, info};
-use crate::{audio::{self, AudioPipelineError, speech_to_text::SttMessage}, global::CONFIG};
+use tokio::{sync::{mpsc::{self, Receiver}, oneshot, watch}, task::{AbortHandle, JoinHandle}, time::Sleep};
+use tracing::{Instrument, debug, error, info, info_span, warn};
+use crate::{audio::{self, AudioBackendError, AudioPipelineError, recorder::AudioInputConfig, speech_to_text::{SpeechToText, SttControl, SttMessage, SttMessageType}}, config::Config};
+
+/// Device callbacks buffered between the recorder and the resampler, enough to ride out
+/// short stalls of the resampler without dropping audio.
+const RECORDER_CHANNEL_CAPACITY: usize = 64;
+
+/// First and longest wait between attempts to reopen a lost input device.
+const RECONNECT_DELAY_MIN: Duration = Duration::from_secs(1);
+const RECONNECT_DELAY_MAX: Duration = Duration::from_secs(30);
+
+pub enum InputDeviceEvent {
+    Lost,
+    Restored,
+}
 
 pub enum CaptureMsg {
     Audio(Vec<f32>),
     Stop,
+    /// Discard the audio recorded so far without transcribing it.
+    Cancel,
     Exit,
 }
 
 pub struct AudioPipeline {
     is_recording: Arc<AtomicBool>,
-    resampled_tx: mpsc::Sender<CaptureMsg>,
+    /// Input of the pipeline, shared with the recorder stream callback.
+    recorder_tx: mpsc::Sender<CaptureMsg>,
+    level_rx: watch::Receiver<f32>,
+    /// Audio callbacks dropped because the pipeline fell behind, over the whole session.
+    dropped_frames: Arc<AtomicU64>,
+    /// `dropped_frames` when the current recording started.
+    dropped_at_start: AtomicU64,
+    abort_handles: Vec<AbortHandle>,
+    device_lost_tx: mpsc::UnboundedSender<()>,
+    stt_control_tx: mpsc::Sender<SttControl>,
+    /// Sender into the STT results, for reporting a reload that failed before reaching the STT task.
+    stt_event_tx: mpsc::Sender<SttMessage>,
+    device_lost_rx: mpsc::UnboundedReceiver<()>,
+    /// Format the resampler was set up for, a reopened device has to match it.
+    input_config: AudioInputConfig,
+    /// Next attempt to reopen the input device, only set while it is lost.
+    reconnect_timer: Option<Pin<Box<Sleep>>>,
+    reconnect_delay: Duration,
+    /// Stop of the last recording waiting out its post-roll, dropping the sender ends it early.
+    pending_stop: Option<(oneshot::Sender<()>, JoinHandle<()>)>,
+    /// Config the pipeline was started with, a model reload only changes the STT task's config.
+    config: Arc<Config>,
     _stream: Stream, // Keep alive
 }
 
 impl AudioPipeline {
-    pub async fn new() -> Result<(Self, Receiver<SttMessage>, JoinHandle<Result<(), AudioPipelineError>>), AudioPipelineError> {
+    pub async fn new(model_path: String, config: Arc<Config>) -> Result<(Self, Receiver<SttMessage>, JoinHandle<Result<(), AudioPipelineError>>), AudioPipelineError> {
+        let stt = SpeechToText::new(model_path, Arc::clone(&config)).await?;
+
         let is_recording = Arc::new(AtomicBool::new(false));
-        let (stt_tx, audio_buffer_rx) = mpsc::channel::<Vec<f32>>(1);
 
-        let (stream, stream_config, recorder_rx) = audio::recorder::init(is_recording.clone())?;
-        let (resampled_tx, resampled_rx, resampler_handle) = audio::resampler::init(
+        let (recorder_tx, recorder_rx) = mpsc::channel::<CaptureMsg>(RECORDER_CHANNEL_CAPACITY);
+        let dropped_frames = Arc::new(AtomicU64::new(0));
+        let (device_lost_tx, device_lost_rx) = mpsc::unbounded_channel();
+        let (stream, stream_config) = audio::recorder::init(
+            recorder_tx.clone(),
+            is_recording.clone(),
+            dropped_frames.clone(),
+            device_lost_tx.clone(),
+            &config,
+        )?;
+        let (resampled_rx, resampler_handle) = audio::resampler::init(
             recorder_rx,
             stream_config.sample_rate as usize,
             stream_config.input_channels,
+            &config,
         ).await?;
+        let (level_tx, level_rx) = watch::channel(0.0);
+        // Capture queues waiting recordings itself, see `queue_recording`, and reports
+        // discarded recordings on the same channel as the STT results.
         let capture_handle = init_audio_capture(
             resampled_rx,
-            stt_tx,
+            stt.audio_sender(),
+            stt.event_sender(),
+            level_tx,
             is_recording.clone(),
+            recorder_tx.clone(),
+            Arc::clone(&config),
         ).await?;
-        let (stt_rx, stt_handle) = audio::speech_to_text::init(audio_buffer_rx).await?;
+        let stt_event_tx = stt.event_sender();
+        let (stt_control_tx, stt_rx, stt_handle) = stt.into_parts();
 
-        let handle = watch_audio_handles(vec![
+        let handles = vec![
             resampler_handle,
             capture_handle,
             stt_handle,
-        ]).await;
+        ];
+        let abort_handles = handles.iter().map(|h| h.abort_handle()).collect();
+        let handle = watch_audio_handles(handles).await;
 
         let pipeline = AudioPipeline {
             is_recording,
-            resampled_tx,
+            recorder_tx,
+            level_rx,
+            dropped_frames,
+            dropped_at_start: AtomicU64::new(0),
+            abort_handles,
+            device_lost_tx,
+            stt_control_tx,
+            stt_event_tx,
+            device_lost_rx,
+            input_config: stream_config,
+            reconnect_timer: None,
+            reconnect_delay: RECONNECT_DELAY_MIN,
+            pending_stop: None,
+            config,
             _stream: stream,
         };
 
         Ok((pipeline, stt_rx, handle))
     }
 
+    /// Stop recording and abort all pipeline tasks.
+    pub fn shutdown(&self) {
+        self.is_recording.store(false, Ordering::Relaxed);
+        let dropped = self.dropped_frames.load(Ordering::Relaxed);
+        if dropped > 0 {
+            warn!("{} audio frames were dropped this session", dropped);
+        }
+        for handle in &self.abort_handles {
+            handle.abort();
+        }
+    }
+
+    /// Smoothed input level of the audio being recorded, 0.0 - 1.0.
+    pub fn input_level(&self) -> watch::Receiver<f32> {
+        self.level_rx.clone()
+    }
+
     /// Start stream and accumulate resampled audio into buffer.
     /// If buffer reaches timeout size, stop recording and transcribe.
-    pub async fn start_recording(&self) {
+    pub async fn start_recording(&mut self) {
+        // The previous recording is stopped first, so the two stay apart.
+        self.finish_pending_stop().await;
+        self.dropped_at_start.store(self.dropped_frames.load(Ordering::Relaxed), Ordering::Relaxed);
         self.is_recording.store(true, Ordering::Relaxed);
     }
 
     /// Stop stream, send accumulated audio_buffer to STT, and clear buffer.
-    pub async fn stop_recording_and_transcribe(&self) {
+    /// Keeps recording for `post_roll_ms` first, so the end of the last word is not cut off.
+    /// Returns right away, the stop is sent from its own task once the post-roll is over.
+    pub async fn stop_recording_and_transcribe(&mut self) {
+        self.finish_pending_stop().await;
+        self.report_dropped_frames();
+        let post_roll = Duration::from_millis(self.config.post_roll_ms);
+        if post_roll.is_zero() {
+            stop_recording(&self.is_recording, &self.recorder_tx).await;
+            return;
+        }
+
+        let (finish_tx, finish_rx) = oneshot::channel();
+        let is_recording = self.is_recording.clone();
+        let recorder_tx = self.recorder_tx.clone();
+        let handle = tokio::spawn(async move {
+            tokio::select! {
+                _ = tokio::time::sleep(post_roll) => {},
+                _ = finish_rx => {},
+            }
+            stop_recording(&is_recording, &recorder_tx).await;
+        });
+        self.pending_stop = Some((finish_tx, handle));
+    }
+
+    /// Stop stream and throw away the accumulated audio without transcribing it.
+    pub async fn cancel_recording(&mut self) {
+        self.finish_pending_stop().await;
         self.is_recording.store(false, Ordering::Relaxed);
-        let _ = self.resampled_tx.send(CaptureMsg::Stop).await;
+        let _ = self.recorder_tx.send(CaptureMsg::Cancel).await;
+    }
+
+    /// Reload the whisper model with freshly read config, which transcription uses from then on.
+    /// `model_path` overrides the one in the config, like `--model` does at startup.
+    /// The STT task reports back with `ModelReloaded` or a `TranscriptionError`.
+    pub async fn reload_model(&self, model_path: Option<&str>) {
+        match reload_config(model_path) {
+            Ok((config, model_path)) => {
+                let _ = self.stt_control_tx.send(SttControl::Reload(Arc::new(config), model_path)).await;
+            },
+            Err(e) => {
+                let _ = self.stt_event_tx.send(SttMessage::new(
+                    SttMessageType::TranscriptionError,
+                    format!("❌ Failed to reload model, keeping the current one: {}", e)
+                )).await;
+            },
+        }
+    }
+
+    /// Resolves when the input device is lost, and again once it could be reopened.
+    /// While the device is lost, reopening is retried with an increasing delay.
+    pub async fn input_device_event(&mut self) -> InputDeviceEvent {
+        if self.reconnect_timer.is_none() {
+            if self.device_lost_rx.recv().await.is_none() {
+                std::future::pending::<()>().await;
+            }
+            // A stream can report several errors while going away.
+            while self.device_lost_rx.try_recv().is_ok() {}
+            // End a post-roll right away, whichever stop gets there first sends the only Stop.
+            self.pending_stop = None;
+
+            // Nothing more arrives from a lost device, so there is no post-roll to wait for.
+            // The stop is sent from its own task: this future must not await anything once the
+            // event was taken, or a competing select branch would drop it half-handled.
+            if self.is_recording.swap(false, Ordering::Relaxed) {
+                self.report_dropped_frames();
+                let recorder_tx = self.recorder_tx.clone();
+                tokio::spawn(async move {
+                    let _ = recorder_tx.send(CaptureMsg::Stop).await;
+                });
+            }
+            self.reconnect_delay = RECONNECT_DELAY_MIN;
+            self.reconnect_timer = Some(Box::pin(tokio::time::sleep(self.reconnect_delay)));
+            return InputDeviceEvent::Lost;
+        }
+
+        loop {
+            if let Some(timer) = &mut self.reconnect_timer {
+                timer.as_mut().await;
+            }
+            match self.reopen_input_device() {
+                Ok(()) => {
+                    self.reconnect_timer = None;
+                    return InputDeviceEvent::Restored;
+                },
+                Err(e) => {
+                    self.reconnect_delay = (self.reconnect_delay * 2).min(RECONNECT_DELAY_MAX);
+                    debug!("Input device still unavailable ({}), retrying in {:?}", e, self.reconnect_delay);
+                    self.reconnect_timer = Some(Box::pin(tokio::time::sleep(self.reconnect_delay)));
+                },
+            }
+        }
     }
+
+    fn reopen_input_device(&mut self) -> Result<(), AudioBackendError> {
+        let (stream, stream_config) = audio::recorder::init(
+            self.recorder_tx.clone(),
+            self.is_recording.clone(),
+            self.dropped_frames.clone(),
+            self.device_lost_tx.clone(),
+            &self.config,
+        )?;
+        if stream_config != self.input_config {
+            return Err(AudioBackendError::InputFormatChanged(stream_config.input_channels, stream_config.sample_rate));
+        }
+        self._stream = stream;
+        Ok(())
+    }
+
+    /// Send a stop still waiting out its post-roll now.
+    async fn finish_pending_stop(&mut self) {
+        if let Some((finish_tx, handle)) = self.pending_stop.take() {
+            drop(finish_tx);
+            let _ = handle.await;
+        }
+    }
+
+    fn report_dropped_frames(&self) {
+        let total = self.dropped_frames.load(Ordering::Relaxed);
+        let dropped = total - self.dropped_at_start.load(Ordering::Relaxed);
+        if dropped > 0 {
+            warn!("{} audio frames dropped during this recording ({} this session), the audio pipeline is falling behind", dropped, total);
+        }
+    }
+}
+
+/// Stop the recording unless something else already did, e.g. the recording timeout, so each
+/// recording gets exactly one Stop.
+async fn stop_recording(is_recording: &AtomicBool, recorder_tx: &mpsc::Sender<CaptureMsg>) {
+    if is_recording.swap(false, Ordering::Relaxed) {
+        let _ = recorder_tx.send(CaptureMsg::Stop).await;
+    }
+}
+
+/// Config for a model reload, read from disk and validated. `model_path` wins over the config's.
+fn reload_config(model_path: Option<&str>) -> Result<(Config, String), String> {
+    let config = Config::load().map_err(|e| e.to_string())?;
+    let model_path = model_path.map_or_else(|| config.model_path.clone(), str::to_string);
+    if let Err(errors) = config.validate(&model_path) {
+        let errors = errors.iter().map(ToString::to_string).collect::<Vec<_>>().join(", ");
+        return Err(format!("invalid config: {}", errors));
+    }
+    for warning in config.model_warnings(&model_path) {
+        warn!("{}", warning);
+    }
+    Ok((config, model_path))
 }
 
 async fn init_audio_capture(
     mut rx: mpsc::Receiver<CaptureMsg>,
     tx: mpsc::Sender<Vec<f32>>,
+    event_tx: mpsc::Sender<SttMessage>,
+    level_tx: watch::Sender<f32>,
     is_recording: Arc<AtomicBool>,
+    recorder_tx: mpsc::Sender<CaptureMsg>,
+    config: Arc<Config>,
 ) -> Result<JoinHandle<Result<(), AudioPipelineError>>, AudioPipelineError> {
     let handle = tokio::spawn(async move {
-        let mut buffer = Vec::<f32>::with_capacity(16_000 * CONFIG.recording_timeout_secs as usize);
+        // Set once the timeout stopped the recording, until its Stop made it through the resampler.
+        let mut timed_out = false;
+        let max_samples = (audio::TARGET_SAMPLE_RATE as f32 * config.recording_timeout_secs) as usize;
+        let mut buffer = Vec::<f32>::with_capacity(max_samples);
+        // Pre-roll and post-roll are part of the buffer, but not of what the user meant to record.
+        let min_ms = config.min_recording_ms + config.pre_roll_ms + config.post_roll_ms;
+        let min_samples = (min_ms as usize).saturating_mul(audio::TARGET_SAMPLE_RATE) / 1000;
+        let mut level = 0.0;
+        let mut high_pass = config.high_pass_filter
+            .then(|| audio::HighPass::new(config.high_pass_cutoff_hz, audio::TARGET_SAMPLE_RATE as f32));
+        let mut noise_gate = config.noise_gate.then(|| audio::NoiseGate::new(
+            config.noise_gate_threshold_db,
+            config.noise_gate_attack_ms,
+            config.noise_gate_release_ms,
+            audio::TARGET_SAMPLE_RATE as f32,
+        ));
+
+        // Finished recordings waiting for the STT task. Capture never waits for transcription,
+        // so a slow model can't stall the recorder. When the queue is full the oldest recording
+        // is dropped, the newest one is most likely the one still worth sending.
+        let mut queue = VecDeque::<Vec<f32>>::new();
 
         debug!("Audio capture task started, waiting for audio data...");
         loop {
-            if let Some(data) = rx.recv().await {
-                match data {
-                    CaptureMsg::Exit => {
-                        error!("Audio capture task received error signal, exiting...");
-                        break;
-                    },
-                    CaptureMsg::Stop => {
-                        if !buffer.is_empty() {
-                            if tx.send(buffer.clone()).await.is_err() {
-                                break;
-                            }
-                            buffer.clear();
-                        }
-                    },
-                    CaptureMsg::Audio(data) => {
-                        buffer.extend_from_slice(&data);
-                        if buffer.len() >= 16_000 * CONFIG.recording_timeout_secs as usize {
-                            debug!("Buffer reached timeout size, sending to STT");
-                            is_recording.store(false, Ordering::Relaxed);
-                            if tx.send(buffer.clone()).await.is_err() {
-                                break;
-                            }
-                            buffer.clear();
-                        }
+            let data = tokio::select! {
+                permit = tx.reserve(), if !queue.is_empty() => {
+                    let Ok(permit) = permit else { break; };
+                    if let Some(recording) = queue.pop_front() {
+                        permit.send(recording);
+                    }
+                    continue;
+                },
+                data = rx.recv() => match data {
+                    Some(data) => data,
+                    None => break,
+                },
+            };
+            match data {
+                // Only sent on a stream error, the pipeline can't continue without its input.
+                CaptureMsg::Exit => {
+                    error!("Audio capture task received error signal, exiting...");
+                    return Err(AudioPipelineError::AudioDevice(AudioBackendError::StreamFailed));
+                },
+                CaptureMsg::Stop => {
+                    timed_out = false;
+                    if let Some(filter) = high_pass.as_mut() {
+                        filter.reset();
+                    }
+                    if let Some(gate) = noise_gate.as_mut() {
+                        gate.reset();
+                    }
+                    level = 0.0;
+                    let _ = level_tx.send(level);
+                    if buffer.len() < min_samples.max(1) {
+                        debug!(samples = buffer.len(), "Recording too short, discarding");
+                        buffer.clear();
+                        let _ = event_tx.send(SttMessage::new(SttMessageType::RecordingTooShort, String::new())).await;
+                    } else {
+                        debug!(samples = buffer.len(), "Recording stopped, sending buffer to STT");
+                        queue_recording(&mut queue, config.transcription_queue_size, buffer.clone(), &event_tx).await;
+                        buffer.clear();
+                    }
+                },
+                CaptureMsg::Cancel => {
+                    debug!("Recording cancelled, discarding {} samples", buffer.len());
+                    timed_out = false;
+                    if let Some(filter) = high_pass.as_mut() {
+                        filter.reset();
+                    }
+                    if let Some(gate) = noise_gate.as_mut() {
+                        gate.reset();
+                    }
+                    level = 0.0;
+                    let _ = level_tx.send(level);
+                    buffer.clear();
+                },
+                CaptureMsg::Audio(mut data) => {
+                    if let Some(filter) = high_pass.as_mut() {
+                        filter.process(&mut data);
+                    }
+                    // Metered before the gate, so a gated but working mic doesn't look dead.
+                    level = smoothed_level(level, &data);
+                    let _ = level_tx.send(level);
+                    if let Some(gate) = noise_gate.as_mut() {
+                        gate.process(&mut data);
+                    }
+                    buffer.extend_from_slice(&data);
+                    if buffer.len() >= max_samples && !timed_out {
+                        debug!(samples = buffer.len(), "Buffer reached timeout size, stopping recording");
+                        timed_out = true;
+                        is_recording.store(false, Ordering::Relaxed);
+                        let _ = event_tx.send(SttMessage::new(SttMessageType::RecordingTimeoutReached, String::new())).await;
+                        // Same path as a manual stop, so the resampler flushes and the buffer is
+                        // queued once the Stop comes back. Sent from its own task, waiting on a
+                        // full recorder channel here could deadlock with the resampler.
+                        let recorder_tx = recorder_tx.clone();
+                        tokio::spawn(async move {
+                            let _ = recorder_tx.send(CaptureMsg::Stop).await;
+                        });
                     }
                 }
             }
         }
 
         Ok(())
-    });
+    }.instrument(info_span!("capture")));
 
     Ok(handle)
 }
 
+/// Add a recording to the transcription queue, dropping the oldest one if it is full.
+async fn queue_recording(queue: &mut VecDeque<Vec<f32>>, max_len: usize, recording: Vec<f32>, event_tx: &mpsc::Sender<SttMessage>) {
+    if queue.len() >= max_len {
+        queue.pop_front();
+        warn!("Transcription queue is full ({} recordings), dropped the oldest recording", max_len);
+        let _ = event_tx.send(SttMessage::new(
+            SttMessageType::TranscriptionError,
+            "Transcription is falling behind, a recording was dropped".into()
+        )).await;
+    }
+    queue.push_back(recording);
+}
+
+/// Meter style level: RMS mapped from -60..0 dBFS to 0..1, rising instantly and decaying smoothly.
+fn smoothed_level(previous: f32, samples: &[f32]) -> f32 {
+    if samples.is_empty() {
+        return previous;
+    }
+
+    let rms = (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt();
+    let db = 20.0 * rms.max(1e-6).log10();
+    let level = ((db + 60.0) / 60.0).clamp(0.0, 1.0);
+    level.max(previous * 0.8)
+}
+
 async fn watch_audio_handles(handles: Vec<JoinHandle<Result<(), AudioPipelineError>>>) -> JoinHandle<Result<(), AudioPipelineError>> {
     tokio::spawn(async move {
         let (completed, _index, remaining) = futures::future::select_all(handles).await;
@@ -0,0 +1,61 @@
+use std::path::Path;
+use tracing::debug;
+
+use crate::audio::{AudioPipelineError, TARGET_SAMPLE_RATE};
+
+/// Audio of the current recording at each resampler stage, kept for `debug_audio_resampling`.
+#[derive(Default)]
+pub struct DebugTaps {
+    /// Interleaved device samples at the device sample rate.
+    pub raw: Vec<f32>,
+    /// Mono samples at the device sample rate.
+    pub downmixed: Vec<f32>,
+    /// Mono samples at `TARGET_SAMPLE_RATE`, what whisper receives.
+    pub resampled: Vec<f32>,
+}
+
+impl DebugTaps {
+    /// Write each stage to `<dir>/<timestamp>-<stage>.wav` and clear the taps.
+    pub fn save(&mut self, dir: &Path, sample_rate: u32, channels: u16) -> Result<(), AudioPipelineError> {
+        if self.raw.is_empty() {
+            return Ok(());
+        }
+
+        std::fs::create_dir_all(dir)
+            .map_err(|e| AudioPipelineError::AudioDebugError(format!("Failed to create {}: {}", dir.display(), e)))?;
+        let timestamp = chrono::Local::now().format("%Y%m%d-%H%M%S-%3f").to_string();
+
+        write_wav(&dir.join(format!("{}-raw.wav", timestamp)), &self.raw, sample_rate, channels)?;
+        write_wav(&dir.join(format!("{}-downmixed.wav", timestamp)), &self.downmixed, sample_rate, 1)?;
+        write_wav(&dir.join(format!("{}-resampled.wav", timestamp)), &self.resampled, TARGET_SAMPLE_RATE as u32, 1)?;
+        debug!("Saved debug audio to {}/{}-*.wav", dir.display(), timestamp);
+
+        self.clear();
+        Ok(())
+    }
+
+    pub fn clear(&mut self) {
+        self.raw.clear();
+        self.downmixed.clear();
+        self.resampled.clear();
+    }
+}
+
+fn write_wav(path: &Path, samples: &[f32], sample_rate: u32, channels: u16) -> Result<(), AudioPipelineError> {
+    let spec = hound::WavSpec {
+        channels,
+        sample_rate,
+        bits_per_sample: 32,
+        sample_format: hound::SampleFormat::Float,
+    };
+    let mut writer = hound::WavWriter::create(path, spec)
+        .map_err(|e| AudioPipelineError::AudioDebugError(format!("Failed to create WAV writer: {}", e)))?;
+    for &sample in samples {
+        writer.write_sample(sample)
+            .map_err(|e| AudioPipelineError::AudioDebugError(format!("Failed to write debug audio into file: {}", e)))?;
+    }
+    writer.finalize()
+        .map_err(|e| AudioPipelineError::AudioDebugError(format!("Failed to finalize WAV file: {}", e)))?;
+
+    Ok(())
+}
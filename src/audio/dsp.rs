@@ -0,0 +1,278 @@
+/// Peaks below this are treated as silence and left alone, so noise isn't amplified.
+const NOISE_FLOOR: f32 = 0.01;
+/// Largest gain normalization applies, about +20 dB.
+const MAX_GAIN: f32 = 10.0;
+
+/// Scale the samples so the loudest one reaches `target_peak` (at most 1.0, so nothing clips).
+pub fn normalize(samples: &mut [f32], target_peak: f32) {
+    let peak = samples.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+    if peak < NOISE_FLOOR {
+        return;
+    }
+
+    let gain = (target_peak.min(1.0) / peak).min(MAX_GAIN);
+    for sample in samples.iter_mut() {
+        *sample = (*sample * gain).clamp(-1.0, 1.0);
+    }
+}
+
+/// Root mean square level of the samples, 0.0 for an empty buffer.
+pub fn rms(samples: &[f32]) -> f32 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+    (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt()
+}
+
+/// First order high-pass filter, removes DC offset and low frequency rumble.
+/// Keeps its state between calls, so a stream can be filtered chunk by chunk.
+pub struct HighPass {
+    alpha: f32,
+    prev_input: f32,
+    prev_output: f32,
+}
+
+impl HighPass {
+    pub fn new(cutoff_hz: f32, sample_rate: f32) -> Self {
+        let rc = 1.0 / (2.0 * std::f32::consts::PI * cutoff_hz);
+        let dt = 1.0 / sample_rate;
+        HighPass {
+            alpha: rc / (rc + dt),
+            prev_input: 0.0,
+            prev_output: 0.0,
+        }
+    }
+
+    pub fn process(&mut self, samples: &mut [f32]) {
+        for sample in samples.iter_mut() {
+            let output = self.alpha * (self.prev_output + *sample - self.prev_input);
+            self.prev_input = *sample;
+            self.prev_output = output;
+            *sample = output;
+        }
+    }
+
+    /// Forget the previous chunk, call between unrelated recordings.
+    pub fn reset(&mut self) {
+        self.prev_input = 0.0;
+        self.prev_output = 0.0;
+    }
+}
+
+/// Lowest noise floor the gate assumes, so digital silence doesn't make every sound open it.
+const MIN_NOISE_FLOOR: f32 = 0.0005;
+/// How quickly the level envelope falls after a peak.
+const ENVELOPE_RELEASE_MS: f32 = 20.0;
+/// How quickly the noise floor estimate follows the level down, and up while the gate is closed.
+const FLOOR_FALL_MS: f32 = 50.0;
+const FLOOR_RISE_MS: f32 = 1000.0;
+/// The floor still rises while the gate is open, but slowly enough that speech doesn't raise it,
+/// so a noise that got louder for good doesn't keep the gate open forever.
+const FLOOR_RISE_OPEN_MS: f32 = 30_000.0;
+/// At first the floor is unknown, it follows the level quickly for this long, which the
+/// pre-roll usually covers before the first word.
+const FLOOR_LEARN_MS: f32 = 200.0;
+
+/// Noise gate with an adaptive threshold. Mutes everything that isn't `threshold_db` louder than
+/// the noise floor, which is estimated from the level while the gate is closed.
+/// Keeps its state between calls, so a stream can be gated chunk by chunk.
+pub struct NoiseGate {
+    threshold: f32,
+    attack: f32,
+    release: f32,
+    envelope_release: f32,
+    floor_fall: f32,
+    floor_rise: f32,
+    floor_rise_open: f32,
+    floor_learn: f32,
+    learn_samples: usize,
+    envelope: f32,
+    noise_floor: f32,
+    gain: f32,
+}
+
+impl NoiseGate {
+    pub fn new(threshold_db: f32, attack_ms: f32, release_ms: f32, sample_rate: f32) -> Self {
+        let coef = |ms: f32| 1.0 - (-1000.0 / (ms * sample_rate)).exp();
+        NoiseGate {
+            threshold: 10f32.powf(threshold_db / 20.0),
+            attack: coef(attack_ms),
+            release: coef(release_ms),
+            envelope_release: coef(ENVELOPE_RELEASE_MS),
+            floor_fall: coef(FLOOR_FALL_MS),
+            floor_rise: coef(FLOOR_RISE_MS),
+            floor_rise_open: coef(FLOOR_RISE_OPEN_MS),
+            floor_learn: coef(FLOOR_LEARN_MS / 4.0),
+            learn_samples: (FLOOR_LEARN_MS * sample_rate / 1000.0) as usize,
+            envelope: 0.0,
+            noise_floor: MIN_NOISE_FLOOR,
+            gain: 0.0,
+        }
+    }
+
+    pub fn process(&mut self, samples: &mut [f32]) {
+        for sample in samples.iter_mut() {
+            let level = sample.abs();
+            if level > self.envelope {
+                self.envelope = level;
+            } else {
+                self.envelope += (level - self.envelope) * self.envelope_release;
+            }
+
+            let open = self.envelope > self.noise_floor * self.threshold;
+            let floor_coef = if self.learn_samples > 0 {
+                self.learn_samples -= 1;
+                self.floor_learn
+            } else if self.envelope < self.noise_floor {
+                self.floor_fall
+            } else if open {
+                self.floor_rise_open
+            } else {
+                self.floor_rise
+            };
+            self.noise_floor += (self.envelope - self.noise_floor) * floor_coef;
+            self.noise_floor = self.noise_floor.max(MIN_NOISE_FLOOR);
+
+            let target = if open { 1.0 } else { 0.0 };
+            let coef = if target > self.gain { self.attack } else { self.release };
+            self.gain += (target - self.gain) * coef;
+            *sample *= self.gain;
+        }
+    }
+
+    /// Close the gate between unrelated recordings, the noise floor estimate is kept.
+    pub fn reset(&mut self) {
+        self.envelope = 0.0;
+        self.gain = 0.0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peak(samples: &[f32]) -> f32 {
+        samples.iter().fold(0.0f32, |peak, s| peak.max(s.abs()))
+    }
+
+    #[test]
+    fn normalize_leaves_silence_alone() {
+        let mut silence = vec![0.0; 1024];
+        normalize(&mut silence, 0.9);
+        assert!(silence.iter().all(|s| *s == 0.0));
+
+        let mut empty: Vec<f32> = Vec::new();
+        normalize(&mut empty, 0.9);
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn normalize_scales_to_the_target_peak() {
+        let mut samples = vec![0.1, -0.25, 0.2];
+        normalize(&mut samples, 0.9);
+        assert!((peak(&samples) - 0.9).abs() < 1e-6);
+        assert!((samples[0] - 0.36).abs() < 1e-6);
+    }
+
+    #[test]
+    fn normalize_never_clips() {
+        let mut samples = vec![0.5, -0.8, 0.3];
+        normalize(&mut samples, 1.5);
+        assert!((peak(&samples) - 1.0).abs() < 1e-6);
+        assert!(samples.iter().all(|s| s.abs() <= 1.0));
+    }
+
+    #[test]
+    fn normalize_limits_the_gain() {
+        let mut samples = vec![0.02, -0.02];
+        normalize(&mut samples, 1.0);
+        assert!((peak(&samples) - 0.02 * MAX_GAIN).abs() < 1e-6);
+    }
+
+    #[test]
+    fn rms_of_known_signals() {
+        assert_eq!(rms(&[]), 0.0);
+        assert_eq!(rms(&[0.0; 16]), 0.0);
+        assert!((rms(&[0.5, -0.5, 0.5, -0.5]) - 0.5).abs() < 1e-6);
+
+        let sine: Vec<f32> = (0..16_000)
+            .map(|i| (2.0 * std::f32::consts::PI * 100.0 * i as f32 / 16_000.0).sin())
+            .collect();
+        assert!((rms(&sine) - std::f32::consts::FRAC_1_SQRT_2).abs() < 1e-3);
+    }
+
+    const RATE: f32 = 16_000.0;
+
+    fn tone(len: usize, amplitude: f32) -> Vec<f32> {
+        (0..len)
+            .map(|i| (2.0 * std::f32::consts::PI * 440.0 * i as f32 / RATE).sin() * amplitude)
+            .collect()
+    }
+
+    /// Constant level background noise, alternating sign so it has no DC.
+    fn noise(len: usize, level: f32) -> Vec<f32> {
+        (0..len).map(|i| if i % 2 == 0 { level } else { -level }).collect()
+    }
+
+    #[test]
+    fn high_pass_removes_dc_offset() {
+        let mut filter = HighPass::new(80.0, RATE);
+        let mut samples = vec![0.5; RATE as usize];
+        filter.process(&mut samples);
+        assert!(samples[samples.len() - 100..].iter().all(|s| s.abs() < 1e-3));
+    }
+
+    #[test]
+    fn high_pass_keeps_state_between_chunks() {
+        let signal: Vec<f32> = tone(4096, 0.5).iter().map(|s| s + 0.2).collect();
+
+        let mut whole = signal.clone();
+        HighPass::new(80.0, RATE).process(&mut whole);
+
+        let mut chunked = signal;
+        let mut filter = HighPass::new(80.0, RATE);
+        for chunk in chunked.chunks_mut(1000) {
+            filter.process(chunk);
+        }
+        assert_eq!(whole, chunked);
+    }
+
+    #[test]
+    fn noise_gate_mutes_noise_and_passes_speech() {
+        let mut gate = NoiseGate::new(10.0, 5.0, 150.0, RATE);
+
+        let mut background = noise(RATE as usize, 0.002);
+        gate.process(&mut background);
+        assert!(peak(&background[background.len() / 2..]) < 1e-4);
+
+        let mut speech = tone(RATE as usize / 2, 0.3);
+        gate.process(&mut speech);
+        assert!(peak(&speech[speech.len() / 2..]) > 0.29);
+    }
+
+    #[test]
+    fn noise_gate_releases_gradually() {
+        let mut gate = NoiseGate::new(10.0, 5.0, 150.0, RATE);
+        gate.process(&mut noise(RATE as usize, 0.002));
+        gate.process(&mut tone(RATE as usize / 2, 0.3));
+
+        // Right after the speech the gate is still mostly open, then it closes.
+        let mut after = noise(RATE as usize, 0.002);
+        gate.process(&mut after);
+        let ms = |ms: usize| ms * RATE as usize / 1000;
+        assert!(after[ms(30)].abs() > 0.001, "gate closed too early");
+        assert!(peak(&after[ms(800)..]) < 1e-4, "gate did not close");
+    }
+
+    #[test]
+    fn noise_gate_reset_closes_the_gate() {
+        let mut gate = NoiseGate::new(10.0, 5.0, 150.0, RATE);
+        gate.process(&mut noise(RATE as usize, 0.002));
+        gate.process(&mut tone(RATE as usize / 2, 0.3));
+        gate.reset();
+
+        let mut background = noise(64, 0.002);
+        gate.process(&mut background);
+        assert!(peak(&background) < 1e-4);
+    }
+}
@@ -0,0 +1,53 @@
+use std::{f32::consts::TAU, time::Duration};
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use tracing::warn;
+
+use crate::audio::AudioBackendError;
+
+/// Fade in/out length, avoids audible clicks at the start and end of a tone.
+const FADE_MS: f32 = 5.0;
+
+/// Play a short sine tone on the default output device without blocking the caller.
+/// The output stream lives on its own thread and is independent of the input stream.
+pub fn play_tone(frequency_hz: f32, duration_ms: u64, volume: f32) {
+    std::thread::spawn(move || {
+        if let Err(e) = play_tone_blocking(frequency_hz, duration_ms, volume) {
+            warn!("Failed to play feedback tone: {}", e);
+        }
+    });
+}
+
+fn play_tone_blocking(frequency_hz: f32, duration_ms: u64, volume: f32) -> Result<(), AudioBackendError> {
+    let host = cpal::default_host();
+    let device = host.default_output_device().ok_or(AudioBackendError::NoOutputDevice)?;
+    let output_config = device.default_output_config()?;
+    let sample_rate = output_config.sample_rate() as f32;
+    let channels = output_config.channels() as usize;
+
+    let total_frames = (sample_rate * duration_ms as f32 / 1000.0) as usize;
+    let fade_frames = ((sample_rate * FADE_MS / 1000.0) as usize).max(1);
+    let mut frame = 0usize;
+
+    let stream = device.build_output_stream(
+        &output_config.into(),
+        move |data: &mut [f32], _| {
+            for out in data.chunks_mut(channels) {
+                let value = if frame < total_frames {
+                    let envelope = (frame.min(total_frames - frame) as f32 / fade_frames as f32).min(1.0);
+                    (frame as f32 / sample_rate * frequency_hz * TAU).sin() * volume * envelope
+                } else {
+                    0.0
+                };
+                out.fill(value);
+                frame += 1;
+            }
+        },
+        |err| warn!("Feedback tone stream error: {}", err),
+        None,
+    )?;
+
+    stream.play()?;
+    std::thread::sleep(Duration::from_millis(duration_ms + 50));
+
+    Ok(())
+}
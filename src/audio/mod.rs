@@ -1,9 +1,19 @@
+use std::path::PathBuf;
 use tokio::task::JoinError;
 
-mod recorder;
+mod debug_wav;
+mod dsp;
+pub mod feedback;
+pub mod recorder;
 mod resampler;
 pub mod speech_to_text;
 pub mod audio_pipeline;
+pub mod wav_input;
+
+pub use dsp::{HighPass, NoiseGate, normalize, rms};
+
+/// Sample rate whisper expects, all audio is resampled to this before transcription.
+pub const TARGET_SAMPLE_RATE: usize = 16_000;
 
 #[derive(Debug, thiserror::Error)]
 pub enum AudioPipelineError {
@@ -16,12 +26,24 @@ pub enum AudioPipelineError {
     #[error("speech-to-text error")]
     SpeechToText(#[from] whisper_rs::WhisperError),
 
-    #[error("model file not found, check you config")]
-    ModelNotFound,
+    #[error("model file not found at {0}, check model_path in your config or download a model from https://huggingface.co/ggerganov/whisper.cpp")]
+    ModelNotFound(PathBuf),
+
+    #[error("model file at {0} can't be read")]
+    ModelUnreadable(PathBuf, #[source] std::io::Error),
+
+    #[error("model file at {0} is only {1} bytes, it is probably incomplete or corrupted")]
+    ModelTooSmall(PathBuf, u64),
+
+    #[error("can't read audio file {0}")]
+    AudioFile(PathBuf, #[source] hound::Error),
 
     #[error("audio debug error")]
     AudioDebugError(String),
 
+    #[error("transcription failed: {0}")]
+    Transcription(String),
+
     #[error("audio pipeline task error")]
     AudioPipelineTaskJoinError(JoinError)
 }
@@ -34,9 +56,21 @@ pub enum AudioBackendError {
     #[error("no audio input device available")]
     NoInputDevice,
 
-    #[error("unsupported number of input channels. Only mono and stereo input devices are supported.")]
+    #[error("no audio output device available")]
+    NoOutputDevice,
+
+    #[error("input device reports no audio channels")]
     UnsupportedInputChannels,
 
+    #[error("unsupported input sample format {0}")]
+    UnsupportedSampleFormat(cpal::SampleFormat),
+
+    #[error("input device came back with a different format ({0} ch, {1} Hz), restart to use it")]
+    InputFormatChanged(usize, u32),
+
+    #[error("audio stream failed, see the log for the backend error")]
+    StreamFailed,
+
     #[error("failed to play audio stream")]
     PlayStream(#[from] cpal::PlayStreamError),
 
@@ -1,74 +1,205 @@
-use std::sync::{Arc, atomic::AtomicBool};
+use std::{collections::VecDeque, sync::{Arc, atomic::{AtomicBool, AtomicU64, Ordering}}};
 
-use cpal::{SampleRate, Stream, traits::{DeviceTrait, HostTrait, StreamTrait}};
-use tokio::{sync::mpsc::{self, Receiver}};
-use tracing::{error, info, warn};
+use cpal::{Device, FromSample, Host, Sample, SampleFormat, SampleRate, SizedSample, Stream, StreamConfig, traits::{DeviceTrait, HostTrait, StreamTrait}};
+use tokio::sync::mpsc::{Sender, UnboundedSender, error::TrySendError};
+use tracing::{error, info, trace_span, warn};
 
-use crate::audio::{AudioBackendError, audio_pipeline::CaptureMsg};
+use crate::{audio::{AudioBackendError, audio_pipeline::CaptureMsg}, config::Config};
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct AudioInputConfig {
     pub input_channels: usize,
     pub sample_rate: SampleRate,
 }
 
+pub struct InputDeviceInfo {
+    pub name: String,
+    pub input_channels: Option<u16>,
+    pub sample_rate: Option<SampleRate>,
+    pub is_default: bool,
+}
+
+/// All available input devices, in the order used for index based selection.
+pub fn list_input_devices() -> Result<Vec<InputDeviceInfo>, AudioBackendError> {
+    let host = cpal::default_host();
+    let default_name = host.default_input_device().map(|device| device_name(&device));
+
+    let devices = host.input_devices()?
+        .map(|device| {
+            let name = device_name(&device);
+            let input_config = device.default_input_config().ok();
+            InputDeviceInfo {
+                is_default: default_name.as_ref() == Some(&name),
+                input_channels: input_config.as_ref().map(|c| c.channels()),
+                sample_rate: input_config.as_ref().map(|c| c.sample_rate()),
+                name,
+            }
+        })
+        .collect();
+
+    Ok(devices)
+}
+
+/// Initialize the input stream on the device from `input_device` in the config.
+/// It is either an index into `list_input_devices` or a case-insensitive substring of the
+/// device name. Falls back to the system default input device if nothing matches.
+/// `device_lost_tx` is notified when the device disappears, e.g. a USB mic is unplugged.
 pub fn init(
+    audio_tx: Sender<CaptureMsg>,
     is_recording: Arc<AtomicBool>,
-) -> Result<(Stream, AudioInputConfig, Receiver<CaptureMsg>), AudioBackendError> {
-    let (audio_tx, audio_rx) = mpsc::channel::<CaptureMsg>(10);
-
+    dropped_frames: Arc<AtomicU64>,
+    device_lost_tx: UnboundedSender<()>,
+    config: &Config,
+) -> Result<(Stream, AudioInputConfig), AudioBackendError> {
     let host = cpal::default_host();
-    let device = match host.default_input_device() {
-        Some(device) => device,
-        None => return Err(AudioBackendError::NoInputDevice),
-    };
+    let device = select_input_device(&host, config.input_device.as_deref())?;
     let input_config = device.default_input_config()?;
     let input_channels = input_config.channels() as usize;
-    if (input_channels != 1) && (input_channels != 2) {
+    if input_channels == 0 {
         return Err(AudioBackendError::UnsupportedInputChannels);
     }
 
     let sample_rate = input_config.sample_rate();
+    let sample_format = input_config.sample_format();
+    let stream_config = input_config.into();
+    let callbacks = StreamCallbacks { audio_tx, is_recording, dropped_frames, device_lost_tx, pre_roll_ms: config.pre_roll_ms };
+    // WASAPI devices often default to integer formats, everything is converted to f32 here.
+    let stream = match sample_format {
+        SampleFormat::F32 => build_input_stream::<f32>(&device, &stream_config, callbacks)?,
+        SampleFormat::I16 => build_input_stream::<i16>(&device, &stream_config, callbacks)?,
+        SampleFormat::U16 => build_input_stream::<u16>(&device, &stream_config, callbacks)?,
+        SampleFormat::I32 => build_input_stream::<i32>(&device, &stream_config, callbacks)?,
+        other => return Err(AudioBackendError::UnsupportedSampleFormat(other)),
+    };
+
+    match stream.play() {
+        Ok(()) => (),
+        Err(e) => error!("Failed to start audio stream: {}", e),
+    }
+
+    info!("Using input device: {} ({})", device_name(&device), sample_format);
+
+    let config = AudioInputConfig {
+        input_channels,
+        sample_rate,
+    };
+
+    Ok((stream, config))
+}
+
+struct StreamCallbacks {
+    audio_tx: Sender<CaptureMsg>,
+    is_recording: Arc<AtomicBool>,
+    dropped_frames: Arc<AtomicU64>,
+    device_lost_tx: UnboundedSender<()>,
+    pre_roll_ms: u64,
+}
+
+fn build_input_stream<T>(device: &Device, config: &StreamConfig, callbacks: StreamCallbacks) -> Result<Stream, AudioBackendError>
+where
+    T: SizedSample,
+    f32: FromSample<T>,
+{
+    let StreamCallbacks { audio_tx, is_recording, dropped_frames, device_lost_tx, pre_roll_ms } = callbacks;
     let audio_tx_clone = audio_tx.clone();
+    // Whole frames of the most recent idle audio, prepended when recording starts.
+    let pre_roll_len = pre_roll_ms as usize * config.sample_rate as usize / 1000 * config.channels as usize;
+    let mut pre_roll = VecDeque::<f32>::with_capacity(pre_roll_len);
+    let mut was_recording = false;
     let stream = device.build_input_stream(
-        &input_config.into(),
-        move |data: &[f32], _| {
-            if is_recording.load(std::sync::atomic::Ordering::Relaxed) {
-                match audio_tx.try_send(CaptureMsg::Audio(data.to_vec())) {
-                    Ok(_) => (),
-                    Err(e) => error!("Failed to send audio data: {}", e),
-                };
+        config,
+        move |data: &[T], _| {
+            let recording = is_recording.load(Ordering::Relaxed);
+            if recording {
+                let _span = trace_span!("recorder_callback", samples = data.len()).entered();
+                let mut samples = Vec::with_capacity(data.len() + pre_roll.len());
+                if !was_recording {
+                    samples.extend(pre_roll.drain(..));
+                }
+                samples.extend(data.iter().map(|&s| s.to_sample::<f32>()));
+                // Never block the audio thread, count the lost frames instead of logging each one.
+                if let Err(TrySendError::Full(_)) = audio_tx.try_send(CaptureMsg::Audio(samples)) {
+                    dropped_frames.fetch_add(1, Ordering::Relaxed);
+                }
+            } else if pre_roll_len > 0 {
+                pre_roll.extend(data.iter().map(|&s| s.to_sample::<f32>()));
+                let excess = pre_roll.len().saturating_sub(pre_roll_len);
+                pre_roll.drain(..excess);
             }
+            was_recording = recording;
         },
         move |err| {
             match err {
-                cpal::StreamError::DeviceNotAvailable => error!("Audio input device not available"),
-                cpal::StreamError::StreamInvalidated => error!("Audio stream invalidated"),
-                cpal::StreamError::BufferUnderrun => error!("Audio buffer underrun"),
-                cpal::StreamError::BackendSpecific { err } => error!("Audio stream error: {}", err),
+                cpal::StreamError::DeviceNotAvailable | cpal::StreamError::StreamInvalidated => {
+                    error!("Audio input device not available: {}", err);
+                    let _ = device_lost_tx.send(());
+                },
+                cpal::StreamError::BufferUnderrun => warn!("Audio buffer underrun"),
+                cpal::StreamError::BackendSpecific { err } => {
+                    error!("Audio stream error: {}", err);
+                    let _ = audio_tx_clone.blocking_send(CaptureMsg::Exit);
+                },
             }
-            let _ = audio_tx_clone.blocking_send(CaptureMsg::Exit);
         },
         None,
     )?;
 
-    match stream.play() {
-        Ok(()) => (),
-        Err(e) => error!("Failed to start audio stream: {}", e),
+    Ok(stream)
+}
+
+fn select_input_device(host: &Host, requested: Option<&str>) -> Result<Device, AudioBackendError> {
+    let devices: Vec<Device> = host.input_devices()?.collect();
+    if devices.is_empty() {
+        return Err(AudioBackendError::NoInputDevice);
+    }
+
+    if let Some(requested) = requested {
+        let names: Vec<String> = devices.iter().map(device_name).collect();
+        let selected = match requested.parse::<usize>() {
+            Ok(index) if index < devices.len() => Some(index),
+            Ok(index) => {
+                warn!("Input device index {} is out of range, {} devices available", index, devices.len());
+                None
+            },
+            Err(_) => {
+                let needle = requested.to_lowercase();
+                let matches: Vec<usize> = names.iter()
+                    .enumerate()
+                    .filter(|(_, name)| name.to_lowercase().contains(&needle))
+                    .map(|(i, _)| i)
+                    .collect();
+                if matches.len() > 1 {
+                    warn!("Input device \"{}\" matches {} devices, using \"{}\"", requested, matches.len(), names[matches[0]]);
+                }
+                matches.first().copied()
+            },
+        };
+
+        match selected {
+            Some(index) => {
+                if let Some(device) = devices.into_iter().nth(index) {
+                    return Ok(device);
+                }
+            },
+            None => warn!("No input device matching \"{}\", falling back to default input device", requested),
+        }
     }
 
-    let name = match device.description() {
+    match host.default_input_device() {
+        Some(device) => Ok(device),
+        None => {
+            warn!("No default input device, using the first available one");
+            host.input_devices()?.next().ok_or(AudioBackendError::NoInputDevice)
+        }
+    }
+}
+
+fn device_name(device: &Device) -> String {
+    match device.description() {
         Ok(desc) => desc.to_string(),
         Err(_) => {
             warn!("Failed to get audio input device name");
             "Unknown Device".to_string()
         }
-    };
-    info!("Using input device: {}", name);
-
-    let config = AudioInputConfig {
-        input_channels,
-        sample_rate,
-    };
-
-    Ok((stream, config, audio_rx))
+    }
 }
@@ -1,87 +1,235 @@
+use std::path::PathBuf;
+
 use rubato::{
     Async, FixedAsync,
     SincInterpolationParameters, SincInterpolationType, WindowFunction,
     Resampler,
 };
-use whisper_rs::convert_stereo_to_mono_audio;
-use tokio::{sync::mpsc::{Sender, Receiver}, task::JoinHandle};
+use tokio::{sync::mpsc::Receiver, task::JoinHandle};
+use tracing::{Instrument, debug, info_span, trace};
+
+use crate::{audio::{AudioPipelineError, ResamplerError, TARGET_SAMPLE_RATE, audio_pipeline::CaptureMsg, debug_wav::DebugTaps}, config::{Config, ResamplerQuality}};
 
-use crate::audio::{AudioPipelineError, audio_pipeline::CaptureMsg};
+const CHUNK_SIZE: usize = 1024;
 
 pub async fn init(
     mut audio_rx: Receiver<CaptureMsg>,
     sample_rate: usize,
     input_channels: usize,
-) -> Result<(Sender<CaptureMsg>, Receiver<CaptureMsg>, JoinHandle<Result<(), AudioPipelineError>>), AudioPipelineError> {
+    config: &Config,
+) -> Result<(Receiver<CaptureMsg>, JoinHandle<Result<(), AudioPipelineError>>), AudioPipelineError> {
     let (resampled_tx, resampled_rx) = tokio::sync::mpsc::channel::<CaptureMsg>(10);
-    let resampled_tx_clone = resampled_tx.clone();
+    let debug_audio = config.debug_audio_resampling;
+    let debug_wav_dir = PathBuf::from(&config.debug_wav_dir);
+    let quality = config.resampler_quality;
     let handle = tokio::spawn(async move {
         let mut input_accum: Vec<f32> = Vec::new();
+        let mut taps = DebugTaps::default();
 
-        let sinc_params = SincInterpolationParameters {
-            sinc_len: 128,
-            f_cutoff: 0.95,
-            interpolation: SincInterpolationType::Linear,
-            oversampling_factor: 256,
-            window: WindowFunction::BlackmanHarris2,
-        };
-
-        let ratio = 16_000.0 / sample_rate as f64;
-        let chunk_size = 1024;
-        let mut resampler = match Async::<f32>::new_sinc(
-            ratio,
-            1.0, // no dynamic ratio range
-            &sinc_params,
-            chunk_size,
-            1, // nbr_channels
-            FixedAsync::Input,
-        ) {
-            Ok(r) => r,
-            Err(e) => { return Err(AudioPipelineError::Resampler(e.into())); }
+        let ratio = TARGET_SAMPLE_RATE as f64 / sample_rate as f64;
+        // Devices already running at 16kHz only need the downmix.
+        let mut resampler = if sample_rate == TARGET_SAMPLE_RATE {
+            debug!("Input is already 16kHz, skipping resampling");
+            None
+        } else {
+            match new_resampler(ratio, quality) {
+                Ok(r) => Some(r),
+                Err(e) => { return Err(AudioPipelineError::Resampler(e)); }
+            }
         };
 
         loop {
             let samples = match audio_rx.recv().await {
                 Some(msg) => match msg {
                     CaptureMsg::Audio(samples) => { samples },
-                    CaptureMsg::Stop => { continue; },
-                    CaptureMsg::Exit => { return Ok(()) }, // exit signal, stop resampling task
+                    CaptureMsg::Stop => {
+                        // Resample whatever is left of the recording before passing the stop on.
+                        if let Some(resampler) = resampler.as_mut() {
+                            let out = flush(resampler, &mut input_accum, ratio)?;
+                            debug!(flushed_samples = out.len(), "Flushed resampler");
+                            if debug_audio {
+                                taps.resampled.extend_from_slice(&out);
+                            }
+                            if !out.is_empty() {
+                                let _ = resampled_tx.send(CaptureMsg::Audio(out)).await;
+                            }
+                        }
+                        if debug_audio {
+                            taps.save(&debug_wav_dir, sample_rate as u32, input_channels as u16)?;
+                        }
+                        let _ = resampled_tx.send(CaptureMsg::Stop).await;
+                        continue;
+                    },
+                    CaptureMsg::Cancel => {
+                        input_accum.clear();
+                        taps.clear();
+                        if let Some(resampler) = resampler.as_mut() {
+                            resampler.reset();
+                        }
+                        let _ = resampled_tx.send(CaptureMsg::Cancel).await;
+                        continue;
+                    },
+                    CaptureMsg::Exit => {
+                        let _ = resampled_tx.send(CaptureMsg::Exit).await;
+                        return Ok(());
+                    }, // exit signal, stop resampling task
                 },
                 None => { return Ok(()); },
             };
 
-            let mono = match input_channels {
-                1 => samples,
-                2 => convert_stereo_to_mono_audio(&samples).expect("should be no half samples missing"),
-                _ => panic!("Unsupported number of input channels: {}", input_channels),
+            if debug_audio {
+                taps.raw.extend_from_slice(&samples);
+            }
+            let mono = downmix_to_mono(samples, input_channels);
+            if debug_audio {
+                taps.downmixed.extend_from_slice(&mono);
+            }
+            let Some(resampler) = resampler.as_mut() else {
+                if debug_audio {
+                    taps.resampled.extend_from_slice(&mono);
+                }
+                let _ = resampled_tx.send(CaptureMsg::Audio(mono)).await;
+                continue;
             };
 
             input_accum.extend_from_slice(&mono);
-            if input_accum.len() < 1024 {
-                continue;
+            while input_accum.len() >= CHUNK_SIZE {
+                let chunk: Vec<f32> = input_accum.drain(..CHUNK_SIZE).collect();
+                let out = resample_chunk(resampler, &chunk)?;
+                trace!(input_samples = chunk.len(), output_samples = out.len(), "Resampled chunk");
+                if debug_audio {
+                    taps.resampled.extend_from_slice(&out);
+                }
+                let _ = resampled_tx.send(CaptureMsg::Audio(out)).await;
             }
+        }
+    }.instrument(info_span!("resampler", sample_rate, input_channels)));
+
+    Ok((resampled_rx, handle))
+}
 
-            let mono: Vec<f32> = input_accum.drain(..1024).collect();
+/// Sinc interpolation presets:
+///
+/// | quality  | sinc_len | f_cutoff | oversampling | window          |
+/// | -------- | -------- | -------- | ------------ | --------------- |
+/// | fast     | 32       | 0.915    | 64           | Hann2           |
+/// | balanced | 64       | 0.925    | 128          | BlackmanHarris2 |
+/// | high     | 128      | 0.95     | 256          | BlackmanHarris2 |
+///
+/// All presets use linear interpolation between the oversampled sinc points.
+fn sinc_params(quality: ResamplerQuality) -> SincInterpolationParameters {
+    let (sinc_len, f_cutoff, oversampling_factor, window) = match quality {
+        ResamplerQuality::Fast => (32, 0.915, 64, WindowFunction::Hann2),
+        ResamplerQuality::Balanced => (64, 0.925, 128, WindowFunction::BlackmanHarris2),
+        ResamplerQuality::High => (128, 0.95, 256, WindowFunction::BlackmanHarris2),
+    };
 
-            // prep output adapters (same shape, but resized to max)
-            let mut out = vec![0.0; resampler.output_frames_max()];
+    SincInterpolationParameters {
+        sinc_len,
+        f_cutoff,
+        interpolation: SincInterpolationType::Linear,
+        oversampling_factor,
+        window,
+    }
+}
+
+fn new_resampler(ratio: f64, quality: ResamplerQuality) -> Result<Async<f32>, ResamplerError> {
+    Ok(Async::<f32>::new_sinc(
+        ratio,
+        1.0, // no dynamic ratio range
+        &sinc_params(quality),
+        CHUNK_SIZE,
+        1, // nbr_channels
+        FixedAsync::Input,
+    )?)
+}
 
-            // process into buffer
-            let (_, out_frames) = match resampler
-                .process_into_buffer(
-                    &audioadapter_buffers::direct::InterleavedSlice::new(&mono, 1, mono.len()).unwrap(),
-                    &mut audioadapter_buffers::direct::InterleavedSlice::new_mut(&mut out, 1, resampler.output_frames_max()).unwrap(),
-                    None,
-                ) {
-                    Ok(r) => r,
-                    Err(e) => { return Err(AudioPipelineError::Resampler(e.into())); }
-                };
+fn resample_chunk(resampler: &mut Async<f32>, chunk: &[f32]) -> Result<Vec<f32>, ResamplerError> {
+    // prep output adapters (same shape, but resized to max)
+    let mut out = vec![0.0; resampler.output_frames_max()];
 
-            out.truncate(out_frames);
-            let _ = resampled_tx_clone.send(CaptureMsg::Audio(out)).await;
-        }
-    });
+    // process into buffer
+    let (_, out_frames) = resampler.process_into_buffer(
+        &audioadapter_buffers::direct::InterleavedSlice::new(chunk, 1, chunk.len()).unwrap(),
+        &mut audioadapter_buffers::direct::InterleavedSlice::new_mut(&mut out, 1, resampler.output_frames_max()).unwrap(),
+        None,
+    )?;
 
-    Ok((resampled_tx, resampled_rx, handle))
+    out.truncate(out_frames);
+    Ok(out)
 }
 
+/// Resample the samples left over after the last full chunk, plus the samples still held
+/// back by the filter delay, and reset the resampler for the next recording.
+/// The leftover samples are padded with silence to a full chunk. Their output lags by the
+/// filter delay, so it is kept up to `remaining * ratio + delay`, the rest is padding.
+fn flush(resampler: &mut Async<f32>, input_accum: &mut Vec<f32>, ratio: f64) -> Result<Vec<f32>, ResamplerError> {
+    let remaining = input_accum.len();
+    let mut chunk = std::mem::take(input_accum);
+    chunk.resize(CHUNK_SIZE, 0.0);
+
+    let wanted = (remaining as f64 * ratio).ceil() as usize + resampler.output_delay();
+    let mut out = resample_chunk(resampler, &chunk)?;
+    // Almost a full chunk was left, part of the delayed tail only comes out of the next one.
+    if out.len() < wanted {
+        out.extend(resample_chunk(resampler, &[0.0; CHUNK_SIZE])?);
+    }
+    out.truncate(wanted);
+
+    resampler.reset();
+    Ok(out)
+}
+
+/// Average interleaved frames of `channels` channels into a single mono channel.
+/// A trailing incomplete frame is dropped.
+fn downmix_to_mono(samples: Vec<f32>, channels: usize) -> Vec<f32> {
+    if channels <= 1 {
+        return samples;
+    }
+
+    samples.chunks_exact(channels)
+        .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn downmix_averages_each_frame() {
+        assert_eq!(downmix_to_mono(vec![1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 1.0, 0.0], 4), [0.25, 0.5]);
+        assert_eq!(downmix_to_mono(vec![0.5, -0.5, 1.0, 0.0], 2), [0.0, 0.5]);
+        assert_eq!(downmix_to_mono(vec![0.1, 0.2], 1), [0.1, 0.2]);
+    }
+
+    #[test]
+    fn flush_keeps_the_end_of_the_signal() {
+        let input_rate = 48_000.0;
+        let ratio = TARGET_SAMPLE_RATE as f64 / input_rate;
+        let mut resampler = new_resampler(ratio, ResamplerQuality::High).unwrap();
+        let delay = resampler.output_delay();
+
+        // Not a multiple of CHUNK_SIZE, so the flush has leftover samples to pad.
+        let len = CHUNK_SIZE * 3 + 500;
+        let signal: Vec<f32> = (0..len)
+            .map(|i| (2.0 * std::f32::consts::PI * 440.0 * i as f32 / input_rate as f32).sin() * 0.5)
+            .collect();
+
+        let mut out = Vec::new();
+        let mut input_accum = Vec::new();
+        for chunk in signal.chunks(CHUNK_SIZE) {
+            input_accum.extend_from_slice(chunk);
+            if input_accum.len() == CHUNK_SIZE {
+                out.extend(resample_chunk(&mut resampler, &input_accum).unwrap());
+                input_accum.clear();
+            }
+        }
+        out.extend(flush(&mut resampler, &mut input_accum, ratio).unwrap());
+
+        let expected = (len as f64 * ratio).ceil() as usize + delay;
+        assert!(out.len().abs_diff(expected) <= 2, "got {} samples, expected about {}", out.len(), expected);
+        let tail_peak = out[out.len() - 16..].iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+        assert!(tail_peak > 0.1, "tail of the signal is missing, peak {}", tail_peak);
+    }
+}
@@ -1,17 +1,50 @@
-use std::fmt::Display;
-use tokio::{sync::mpsc::{self, Receiver}, task::JoinHandle};
-use tracing::info;
-use whisper_rs::{FullParams, WhisperContext, WhisperContextParameters, install_logging_hooks};
-use crate::{audio::{AudioPipelineError}, global::CONFIG};
+use std::{fmt::Display, path::Path, sync::Arc, time::Instant};
+use tokio::{sync::mpsc::{self, Receiver, Sender}, task::JoinHandle};
+use tracing::{Instrument, debug, debug_span, info, info_span, warn};
+use whisper_rs::{FullParams, SamplingStrategy, WhisperContext, WhisperContextParameters, WhisperSegment, WhisperState, install_logging_hooks};
+use crate::{audio::{self, AudioPipelineError, TARGET_SAMPLE_RATE}, config::Config, text, transcripts::{self, TranscriptMeta}};
 
 pub enum SttMessageType {
     TranscriptionError,
     TranscriptionResult,
+    /// Transcription finished but contained no speech, or only a low-confidence guess.
+    NoSpeech,
+    /// Recording was stopped automatically after `recording_timeout_secs`.
+    RecordingTimeoutReached,
+    /// Recording was shorter than `min_recording_ms` and was discarded without transcribing.
+    RecordingTooShort,
+    /// The model was reloaded, content is the model path. A failed reload is a `TranscriptionError`.
+    ModelReloaded,
+}
+
+/// Requests to the STT task, separate from the audio it transcribes.
+pub enum SttControl {
+    /// Load the model at the given path again and switch to the given config for everything
+    /// that follows, see `reload_model`.
+    Reload(Arc<Config>, String),
 }
 
 pub struct SttMessage {
     pub msg_type: SttMessageType,
     pub content: String,
+    /// Only collected when `collect_segments` is enabled in config.
+    pub segments: Vec<SegmentInfo>,
+    /// Length of the transcribed audio.
+    pub audio_ms: u64,
+    /// Time whisper spent transcribing the audio, including the fallback model and alternatives.
+    pub processing_ms: u64,
+    /// Other candidate transcriptions, only decoded when `max_alternatives` is above 1 and the
+    /// confidence is below `alternatives_confidence`.
+    pub alternatives: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SegmentInfo {
+    pub text: String,
+    pub start_ms: i64,
+    pub end_ms: i64,
+    /// Average token probability in the segment, 0.0 - 1.0
+    pub avg_confidence: f32,
 }
 
 impl Display for SttMessage {
@@ -19,97 +52,553 @@ impl Display for SttMessage {
         match self.msg_type {
             SttMessageType::TranscriptionError => write!(f, "[STT ERROR] {}", self.content),
             SttMessageType::TranscriptionResult => write!(f, "[STT TRANSCRIPTION] {}", self.content),
+            SttMessageType::NoSpeech => write!(f, "[STT NO SPEECH] {}", self.content),
+            SttMessageType::RecordingTimeoutReached => write!(f, "[STT RECORDING TIMEOUT] {}", self.content),
+            SttMessageType::RecordingTooShort => write!(f, "[STT RECORDING TOO SHORT] {}", self.content),
+            SttMessageType::ModelReloaded => write!(f, "[STT MODEL RELOADED] {}", self.content),
         }
     }
 }
 
 impl SttMessage {
     pub fn new(msg_type: SttMessageType, content: String) -> Self {
-        Self { msg_type, content }
+        Self { msg_type, content, segments: Vec::new(), audio_ms: 0, processing_ms: 0, alternatives: Vec::new() }
     }
 }
 
-pub async fn init(
-    mut audio_in: Receiver<Vec<f32>>
-) -> Result<(Receiver<SttMessage>, JoinHandle<Result<(), AudioPipelineError>>), AudioPipelineError> {
-    let (event_tx, event_rx) = mpsc::channel::<SttMessage>(1);
+/// Speech-to-text on its own, without audio capture or InSim: submit 16kHz mono recordings and
+/// read the results from `results` in the order they were submitted.
+/// Decoding and post-processing follow the config it was created with, or reloaded with.
+pub struct SpeechToText {
+    audio_tx: Sender<Vec<f32>>,
+    control_tx: Sender<SttControl>,
+    event_tx: Sender<SttMessage>,
+    results: Receiver<SttMessage>,
+    handle: JoinHandle<Result<(), AudioPipelineError>>,
+}
+
+impl SpeechToText {
+    /// Check the model file and start the STT task, which loads the model.
+    /// The model is loaded from `model_path` rather than the config's, nothing is read from `config.toml`.
+    pub async fn new(model_path: String, config: Arc<Config>) -> Result<Self, AudioPipelineError> {
+        check_model_file(&model_path)?;
+        let (audio_tx, audio_rx) = mpsc::channel(1);
+        let (control_tx, control_rx) = mpsc::channel(1);
+        let (event_tx, results) = mpsc::channel(4);
+        let handle = init(audio_rx, control_rx, event_tx.clone(), model_path, config).await?;
+
+        Ok(SpeechToText { audio_tx, control_tx, event_tx, results, handle })
+    }
+
+    /// Queue samples for transcription, waits while an earlier recording is being transcribed.
+    /// Returns false once the STT task has stopped.
+    pub async fn submit(&self, samples: Vec<f32>) -> bool {
+        self.audio_tx.send(samples).await.is_ok()
+    }
+
+    /// Load the model again with a new config, reported back as `ModelReloaded`
+    /// or a `TranscriptionError` in `results`.
+    pub async fn reload(&self, config: Arc<Config>, model_path: String) {
+        let _ = self.control_tx.send(SttControl::Reload(config, model_path)).await;
+    }
+
+    pub fn results(&mut self) -> &mut Receiver<SttMessage> {
+        &mut self.results
+    }
+
+    /// Sender for `submit`, for callers that queue recordings themselves.
+    pub fn audio_sender(&self) -> Sender<Vec<f32>> {
+        self.audio_tx.clone()
+    }
 
+    /// Sender into `results`, for reporting events around transcription (e.g. a discarded
+    /// recording) in order with the results.
+    pub fn event_sender(&self) -> Sender<SttMessage> {
+        self.event_tx.clone()
+    }
+
+    /// Control sender, results and task handle, for wiring them into separate owners.
+    /// The STT task stops once every audio sender is dropped.
+    pub fn into_parts(self) -> (Sender<SttControl>, Receiver<SttMessage>, JoinHandle<Result<(), AudioPipelineError>>) {
+        (self.control_tx, self.results, self.handle)
+    }
+}
+
+/// Transcribe 16kHz mono samples in one go, e.g. samples read from a WAV file.
+/// Loads the model on every call and applies the same post-processing as the pipeline.
+/// Audio without speech gives an empty string.
+pub async fn transcribe_samples(samples: &[f32], model_path: String, config: Arc<Config>) -> Result<String, AudioPipelineError> {
+    let mut stt = SpeechToText::new(model_path, config).await?;
+    let task_ended = if stt.submit(samples.to_vec()).await {
+        tokio::select! {
+            biased;
+            Some(msg) = stt.results.recv() => {
+                return match msg.msg_type {
+                    SttMessageType::TranscriptionResult => Ok(msg.content),
+                    SttMessageType::NoSpeech => Ok(String::new()),
+                    _ => Err(AudioPipelineError::Transcription(msg.content)),
+                };
+            },
+            res = &mut stt.handle => res,
+        }
+    } else {
+        stt.handle.await
+    };
+
+    // The STT task only stops on its own when something went wrong.
+    match task_ended {
+        Ok(Ok(())) => Err(AudioPipelineError::Transcription("speech-to-text stopped before transcribing".into())),
+        Ok(Err(e)) => Err(e),
+        Err(e) => Err(AudioPipelineError::AudioPipelineTaskJoinError(e)),
+    }
+}
+
+/// Runs the STT task. Buffers are transcribed one at a time in the order they were recorded,
+/// while capture and resampling keep running on their own tasks.
+/// The whisper state is moved into a blocking thread for each transcription and handed back
+/// afterwards, so it is only ever used by one thread at a time and the async runtime is never
+/// blocked by whisper.
+async fn init(
+    mut audio_in: Receiver<Vec<f32>>,
+    mut control_rx: Receiver<SttControl>,
+    event_tx: Sender<SttMessage>,
+    model_path: String,
+    config: Arc<Config>,
+) -> Result<JoinHandle<Result<(), AudioPipelineError>>, AudioPipelineError> {
     let handle = tokio::spawn(async move {
         install_logging_hooks();
-        let mut params = WhisperContextParameters::new();
-        params.use_gpu(CONFIG.use_gpu);
-        // check if model path exists:
-        if !std::path::Path::new(&CONFIG.model_path).exists() {
-            return Err(AudioPipelineError::ModelNotFound);
-        }
-        let whisper_ctx = WhisperContext::new_with_params(CONFIG.model_path.as_str(), params)?;
-        let mut whisper_state = match whisper_ctx.create_state() {
-            Ok(state) => state,
-            Err(err) => {return Err(err.into());}
-        };
-        let mut full_params = FullParams::new(whisper_rs::SamplingStrategy::Greedy { best_of: 8 });
-        full_params.set_language(Some("en"));
-        full_params.set_print_special(false);
-        full_params.set_print_progress(false);
-        full_params.set_print_realtime(false);
-        full_params.set_print_timestamps(false);
+        let mut model_path = model_path;
+        let (mut whisper_ctx, mut whisper_state, mut fallback_state) = load_models(&model_path, &config)?;
+        let mut settings = Arc::new(Settings::new(config));
 
         info!("✅ STT thread started");
 
+        let mut consecutive_errors: u8 = 0;
         loop {
-            while let Some(audio_buffer) = audio_in.recv().await {
-                match maybe_dump_buffer_to_wav(&audio_buffer) {
-                    Ok(_) => (),
-                    Err(err) => { return Err(err); }
-                };
-                if let Err(err) = whisper_state.full(full_params.clone(), &audio_buffer) {
-                    let _ = event_tx.send(
-                        SttMessage::new(
-                            SttMessageType::TranscriptionError,
-                            format!("❌ Transcription error: {:?}", err)
-                        )
-                    ).await;
+            let mut audio_buffer = tokio::select! {
+                Some(SttControl::Reload(config, path)) = control_rx.recv() => {
+                    match reload_model(&path, &config).await {
+                        Ok((ctx, state, fallback)) => {
+                            info!("Reloaded whisper model from {}", path);
+                            let _ = event_tx.send(SttMessage::new(SttMessageType::ModelReloaded, path.clone())).await;
+                            model_path = path;
+                            whisper_ctx = ctx;
+                            whisper_state = state;
+                            fallback_state = fallback;
+                            settings = Arc::new(Settings::new(config));
+                            consecutive_errors = 0;
+                        },
+                        Err(e) => {
+                            let _ = event_tx.send(SttMessage::new(
+                                SttMessageType::TranscriptionError,
+                                format!("❌ Failed to reload model, keeping {}: {}", model_path, e)
+                            )).await;
+                        },
+                    }
                     continue;
+                },
+                audio_buffer = audio_in.recv() => match audio_buffer {
+                    Some(audio_buffer) => audio_buffer,
+                    None => break,
+                },
+            };
+            let config = Arc::clone(&settings.config);
+            let level = audio::rms(&audio_buffer);
+            if level < config.silence_threshold {
+                debug!(samples = audio_buffer.len(), level, "Recording is silent, skipping transcription");
+                let _ = event_tx.send(SttMessage::new(SttMessageType::NoSpeech, String::new())).await;
+                continue;
+            }
+            maybe_normalize(&mut audio_buffer, &config);
+            let audio_ms = (audio_buffer.len() * 1000 / TARGET_SAMPLE_RATE) as u64;
+            let started = Instant::now();
+            let span = debug_span!("transcribe", samples = audio_buffer.len(), audio_ms);
+            let decode = Arc::clone(&settings);
+            let (state, audio, result) = tokio::task::spawn_blocking(move || {
+                let result = span.in_scope(|| whisper_state.full(decode.full_params(), &audio_buffer));
+                (whisper_state, audio_buffer, result)
+            })
+                .await
+                .map_err(AudioPipelineError::AudioPipelineTaskJoinError)?;
+            whisper_state = state;
+            let mut audio_buffer = audio;
+            let processing_ms = started.elapsed().as_millis() as u64;
+            info!("Transcribed {}ms of audio in {}ms", audio_ms, processing_ms);
+            if let Err(err) = result {
+                let _ = event_tx.send(
+                    SttMessage::new(
+                        SttMessageType::TranscriptionError,
+                        format!("❌ Transcription error: {:?}", err)
+                    )
+                ).await;
+
+                consecutive_errors += 1;
+                if consecutive_errors >= config.max_transcription_errors {
+                    warn!("{} consecutive transcription errors, recreating whisper state", consecutive_errors);
+                    whisper_state = whisper_ctx.create_state()?;
+                    consecutive_errors = 0;
+                    info!("Whisper state recreated");
                 }
+                continue;
+            }
+            consecutive_errors = 0;
 
-                let mut text = String::new();
-                let n_segments = whisper_state.full_n_segments();
-                for i in 0..n_segments {
-                    if let Some(segment) = whisper_state.get_segment(i) && let Ok(segment) = segment.to_str() {
-                        text.push_str(segment);
-                    }
+            // Confidence is also needed to decide when to escalate to the fallback model
+            // or to decode alternatives.
+            let check_confidence = config.min_confidence > 0.0 || fallback_state.is_some() || config.max_alternatives > 1;
+            let mut transcription = read_segments(&whisper_state, &config, check_confidence);
+            let mut model = model_path.as_str();
+
+            if let Some(confidence) = transcription.confidence
+                && confidence < config.fallback_confidence
+                && let Some(mut state) = fallback_state.take()
+            {
+                let fallback_path = config.fallback_model_path.as_deref().unwrap_or_default();
+                info!("Low confidence ({:.2}), retrying with fallback model {}", confidence, fallback_path);
+                let decode = Arc::clone(&settings);
+                let (state, audio, result) = tokio::task::spawn_blocking(move || {
+                    let result = state.full(decode.full_params(), &audio_buffer)
+                        .map(|_| read_segments(&state, &decode.config, true));
+                    (state, audio_buffer, result)
+                })
+                    .await
+                    .map_err(AudioPipelineError::AudioPipelineTaskJoinError)?;
+                fallback_state = Some(state);
+                audio_buffer = audio;
+                match result {
+                    Ok(fallback) => {
+                        transcription = fallback;
+                        model = fallback_path;
+                    },
+                    Err(err) => warn!("Fallback model failed, keeping the original result: {:?}", err),
                 }
+            }
 
+            if config.min_confidence > 0.0
+                && let Some(confidence) = transcription.confidence
+                && confidence < config.min_confidence
+            {
                 let _ = event_tx.send(
                     SttMessage::new(
-                        SttMessageType::TranscriptionResult,
-                        text.trim().to_string()
+                        SttMessageType::NoSpeech,
+                        format!("Discarded low-confidence result ({:.2}): {}", confidence, transcription.text.trim())
                     )
                 ).await;
+                continue;
             }
+
+            let text = post_process(&transcription.text, &config);
+            if text.is_empty() {
+                let _ = event_tx.send(
+                    SttMessage::new(SttMessageType::NoSpeech, String::new())
+                ).await;
+                continue;
+            }
+
+            transcripts::log_result(&config, &text, TranscriptMeta { model });
+
+            let mut msg = SttMessage::new(
+                SttMessageType::TranscriptionResult,
+                text
+            );
+            if config.max_alternatives > 1
+                && transcription.confidence.is_some_and(|confidence| confidence < config.alternatives_confidence)
+            {
+                let decode = Arc::clone(&settings);
+                let primary = msg.content.clone();
+                let (state, alternatives) = tokio::task::spawn_blocking(move || {
+                    let alternatives = decode_alternatives(&mut whisper_state, &decode, &audio_buffer, &primary);
+                    (whisper_state, alternatives)
+                })
+                    .await
+                    .map_err(AudioPipelineError::AudioPipelineTaskJoinError)?;
+                whisper_state = state;
+                msg.alternatives = alternatives;
+            }
+            msg.segments = transcription.segments;
+            msg.audio_ms = audio_ms;
+            msg.processing_ms = started.elapsed().as_millis() as u64;
+            let _ = event_tx.send(msg).await;
         }
-    });
 
-    Ok((event_rx, handle))
+        info!("STT input closed, stopping STT thread");
+        Ok(())
+    }.instrument(info_span!("stt")));
+
+    Ok(handle)
 }
 
-fn maybe_dump_buffer_to_wav(samples: &[f32]) -> Result<(), AudioPipelineError> {
-    if !CONFIG.debug_audio_resampling { return Ok(()); }
+/// Config the STT task works with, replaced as a whole when the model is reloaded.
+struct Settings {
+    config: Arc<Config>,
+    /// `initial_prompt` combined with the contents of `initial_prompt_path`, read once.
+    initial_prompt: Option<String>,
+    n_threads: usize,
+}
 
-    let spec = hound::WavSpec {
-        channels: 1,
-        sample_rate: 16_000,
-        bits_per_sample: 32,
-        sample_format: hound::SampleFormat::Float,
+impl Settings {
+    fn new(config: Arc<Config>) -> Self {
+        // Whisper is compute bound, hyper-threads add little over the physical cores.
+        let n_threads = config.n_threads.unwrap_or_else(num_cpus::get_physical);
+        info!("Using {} CPU threads for whisper", n_threads);
+        let initial_prompt = initial_prompt(&config);
+        if let Some(prompt) = &initial_prompt {
+            info!("Using initial prompt: {}", prompt);
+        }
+        Settings { config, initial_prompt, n_threads }
+    }
+
+    fn full_params(&self) -> FullParams<'_, '_> {
+        let mut full_params = FullParams::new(sampling_strategy(&self.config));
+        full_params.set_language(self.config.language.as_deref());
+        full_params.set_translate(self.config.translate);
+        full_params.set_n_threads(self.n_threads as i32);
+        full_params.set_print_special(false);
+        full_params.set_print_progress(false);
+        full_params.set_print_realtime(false);
+        full_params.set_print_timestamps(false);
+        if let Some(prompt) = &self.initial_prompt {
+            full_params.set_initial_prompt(prompt);
+        }
+        full_params
+    }
+}
+
+/// Text of all segments of the last transcription.
+fn state_text(whisper_state: &WhisperState) -> String {
+    (0..whisper_state.full_n_segments())
+        .filter_map(|i| whisper_state.get_segment(i))
+        .filter_map(|segment| segment.to_str().ok().map(String::from))
+        .collect()
+}
+
+/// Whisper only returns its best candidate, so alternatives come from decoding the same audio
+/// again at increasing temperatures. Duplicates and empty results are dropped.
+fn decode_alternatives(whisper_state: &mut WhisperState, settings: &Settings, samples: &[f32], primary: &str) -> Vec<String> {
+    let mut alternatives: Vec<String> = Vec::new();
+    for i in 1..settings.config.max_alternatives {
+        let mut params = settings.full_params();
+        params.set_temperature(ALTERNATIVE_TEMPERATURE_STEP * i as f32);
+        if let Err(err) = whisper_state.full(params, samples) {
+            warn!("Failed to decode alternative transcription: {:?}", err);
+            break;
+        }
+        let text = post_process(&state_text(whisper_state), &settings.config);
+        if !text.is_empty() && text != primary && !alternatives.contains(&text) {
+            alternatives.push(text);
+        }
+    }
+    debug!("Decoded {} alternative transcriptions", alternatives.len());
+    alternatives
+}
+
+/// Main model, its state and the fallback model's state, if `fallback_model_path` is set.
+fn load_models(model_path: &str, config: &Config) -> Result<(WhisperContext, WhisperState, Option<WhisperState>), whisper_rs::WhisperError> {
+    info!("Loading whisper model from {}", model_path);
+    let ctx = load_whisper_context(model_path, config.use_gpu)?;
+    let state = ctx.create_state()?;
+    let fallback_state = match config.fallback_model_path.as_deref() {
+        Some(path) => {
+            info!("Loading fallback whisper model from {}", path);
+            Some(load_whisper_context(path, config.use_gpu)?.create_state()?)
+        },
+        None => None,
     };
-    let mut writer = hound::WavWriter::create("debug.wav", spec)
-        .map_err(|e| AudioPipelineError::AudioDebugError(format!("Failed to create WAV writer: {}", e)))?;
-    for &sample in samples {
-        writer.write_sample(sample)
-            .map_err(|e| AudioPipelineError::AudioDebugError(format!("Failed to write debug audio into file: {}", e)))?;
+    Ok((ctx, state, fallback_state))
+}
+
+/// Load the models for a reloaded config off the async runtime, so the model and every
+/// transcription setting can be changed without a restart. The caller only switches over
+/// once everything loaded, a failed reload keeps the current models.
+async fn reload_model(model_path: &str, config: &Arc<Config>) -> Result<(WhisperContext, WhisperState, Option<WhisperState>), AudioPipelineError> {
+    check_model_file(model_path)?;
+    let path = model_path.to_string();
+    let config = Arc::clone(config);
+    let models = tokio::task::spawn_blocking(move || load_models(&path, &config))
+        .await
+        .map_err(AudioPipelineError::AudioPipelineTaskJoinError)??;
+    Ok(models)
+}
+
+fn maybe_normalize(samples: &mut [f32], config: &Config) {
+    if config.normalize_audio {
+        audio::normalize(samples, config.normalize_target_peak);
+    }
+}
+
+/// Non-speech removal, replacements and punctuation, as configured.
+fn post_process(text: &str, config: &Config) -> String {
+    let text = if config.strip_non_speech {
+        text::strip_non_speech(text)
+    } else {
+        text.trim().to_string()
+    };
+    let text = if config.remove_fillers {
+        text::remove_fillers(&text, &config.fillers)
+    } else {
+        text
+    };
+    let mut text = text::apply_replacements(&text, &config.replacements);
+    if config.words_to_digits {
+        text = text::words_to_digits(&text);
+    }
+    if config.auto_punctuate {
+        text = text::tidy(&text);
+    }
+    text::apply_case(&text, config.text_case)
+}
+
+/// Text of a finished transcription, with per-segment details when they are needed.
+struct Transcription {
+    text: String,
+    segments: Vec<SegmentInfo>,
+    /// Average of the segment confidences, only read when `with_confidence` was set.
+    confidence: Option<f32>,
+}
+
+fn read_segments(whisper_state: &WhisperState, config: &Config, with_confidence: bool) -> Transcription {
+    let mut text = String::new();
+    let mut segments = Vec::new();
+    let mut confidences = Vec::new();
+    for i in 0..whisper_state.full_n_segments() {
+        let Some(segment) = whisper_state.get_segment(i) else { continue; };
+        let Ok(segment_text) = segment.to_str() else { continue; };
+        text.push_str(segment_text);
+
+        if !config.collect_segments && !with_confidence {
+            continue;
+        }
+        let avg_confidence = segment_confidence(&segment);
+        confidences.push(avg_confidence);
+
+        if config.collect_segments {
+            let info = SegmentInfo {
+                text: segment_text.trim().to_string(),
+                // whisper timestamps are in centiseconds
+                start_ms: segment.start_timestamp() * 10,
+                end_ms: segment.end_timestamp() * 10,
+                avg_confidence,
+            };
+            debug!("Segment {:?}", info);
+            segments.push(info);
+        }
+    }
+
+    let confidence = (with_confidence && !confidences.is_empty())
+        .then(|| confidences.iter().sum::<f32>() / confidences.len() as f32);
+    Transcription { text, segments, confidence }
+}
+
+fn segment_confidence(segment: &WhisperSegment) -> f32 {
+    let n_tokens = segment.n_tokens();
+    if n_tokens <= 0 {
+        return 0.0;
+    }
+
+    let total: f32 = (0..n_tokens)
+        .filter_map(|i| segment.get_token(i))
+        .map(|token| token.token_probability())
+        .sum();
+    total / n_tokens as f32
+}
+
+/// Temperature added for every further alternative transcription.
+const ALTERNATIVE_TEMPERATURE_STEP: f32 = 0.3;
+
+/// Smallest official ggml model (tiny) is ~75MB, anything below this is not a usable model.
+const MIN_MODEL_SIZE_BYTES: u64 = 1024 * 1024;
+
+/// Check that the model file exists and looks like a model, before any pipeline task is spawned.
+pub fn check_model_file(model_path: &str) -> Result<(), AudioPipelineError> {
+    let path = std::path::absolute(Path::new(model_path))
+        .unwrap_or_else(|_| Path::new(model_path).to_path_buf());
+    if !path.is_file() {
+        return Err(AudioPipelineError::ModelNotFound(path));
+    }
+
+    let size = match std::fs::File::open(&path).and_then(|file| file.metadata()) {
+        Ok(metadata) => metadata.len(),
+        Err(e) => return Err(AudioPipelineError::ModelUnreadable(path, e)),
+    };
+    if size < MIN_MODEL_SIZE_BYTES {
+        return Err(AudioPipelineError::ModelTooSmall(path, size));
     }
-    writer.finalize()
-        .map_err(|e| AudioPipelineError::AudioDebugError(format!("Failed to finalize WAV file: {}", e)))?;
 
     Ok(())
 }
+
+/// Load the model, retrying once on CPU if GPU initialization fails.
+fn load_whisper_context(model_path: &str, use_gpu: bool) -> Result<WhisperContext, whisper_rs::WhisperError> {
+    let mut params = WhisperContextParameters::new();
+    params.use_gpu(use_gpu);
+    match WhisperContext::new_with_params(model_path, params) {
+        Ok(ctx) => Ok(ctx),
+        Err(err) if use_gpu => {
+            warn!("Failed to load whisper model with GPU acceleration ({:?}), falling back to CPU", err);
+            let mut params = WhisperContextParameters::new();
+            params.use_gpu(false);
+            WhisperContext::new_with_params(model_path, params)
+        },
+        Err(err) => Err(err),
+    }
+}
+
+fn sampling_strategy(config: &Config) -> SamplingStrategy {
+    match config.sampling.as_str() {
+        "greedy" => SamplingStrategy::Greedy { best_of: config.best_of as i32 },
+        "beam" => SamplingStrategy::BeamSearch { beam_size: config.beam_size as i32, patience: -1.0 },
+        other => {
+            warn!("Unknown sampling strategy \"{}\", falling back to greedy", other);
+            SamplingStrategy::Greedy { best_of: config.best_of as i32 }
+        },
+    }
+}
+
+/// Combines `initial_prompt` and the contents of `initial_prompt_path`, ignoring blank values.
+fn initial_prompt(config: &Config) -> Option<String> {
+    let mut parts = Vec::new();
+    if let Some(prompt) = &config.initial_prompt {
+        parts.push(prompt.trim().to_string());
+    }
+    if let Some(path) = &config.initial_prompt_path {
+        match std::fs::read_to_string(path) {
+            Ok(contents) => parts.push(contents.split_whitespace().collect::<Vec<_>>().join(" ")),
+            Err(e) => warn!("Failed to read initial prompt from {}: {}", path, e),
+        }
+    }
+
+    let prompt = parts.into_iter()
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ");
+    if prompt.is_empty() { None } else { Some(prompt) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::wav_input;
+
+    /// Recordings in `tests/fixtures` and their expected transcription, compared ignoring case
+    /// and punctuation.
+    const FIXTURES: &[(&str, &str)] = &[
+        ("silence.wav", ""),
+    ];
+
+    /// Needs a whisper model, from `LFS_STT_TEST_MODEL` or `model_path` in config.example.toml.
+    /// Run with `cargo test -- --ignored`.
+    #[tokio::test]
+    #[ignore]
+    async fn transcribes_wav_fixtures() {
+        let config: Config = toml::from_str(include_str!("../../config.example.toml")).unwrap();
+        let model_path = std::env::var("LFS_STT_TEST_MODEL").unwrap_or_else(|_| config.model_path.clone());
+        let config = Arc::new(config);
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+
+        for (file, expected) in FIXTURES {
+            let samples = wav_input::load(&dir.join(file), &config).await.unwrap();
+            let text = transcribe_samples(&samples, model_path.clone(), Arc::clone(&config)).await.unwrap();
+            assert_eq!(text::normalize_phrase(&text), text::normalize_phrase(expected), "{}", file);
+        }
+    }
+}
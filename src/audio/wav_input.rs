@@ -0,0 +1,54 @@
+use std::path::Path;
+use tokio::sync::mpsc;
+use tracing::debug;
+
+use crate::{audio::{AudioPipelineError, audio_pipeline::CaptureMsg, resampler}, config::Config};
+
+/// Frames per chunk fed to the resampler, about what an audio device callback delivers.
+const FEED_CHUNK_FRAMES: usize = 1024;
+
+/// Read a WAV file and convert it to 16kHz mono through the same resampler as live input.
+pub async fn load(path: &Path, config: &Config) -> Result<Vec<f32>, AudioPipelineError> {
+    let (samples, sample_rate, channels) = read_wav(path)?;
+    debug!(path = %path.display(), sample_rate, channels, samples = samples.len(), "Read WAV file");
+
+    let (input_tx, input_rx) = mpsc::channel(8);
+    let (mut resampled_rx, handle) = resampler::init(input_rx, sample_rate as usize, channels as usize, config).await?;
+    // Fed from its own task, the resampler only buffers a few chunks before it waits for us.
+    let chunk_len = FEED_CHUNK_FRAMES * channels as usize;
+    tokio::spawn(async move {
+        for chunk in samples.chunks(chunk_len) {
+            if input_tx.send(CaptureMsg::Audio(chunk.to_vec())).await.is_err() {
+                return;
+            }
+        }
+        let _ = input_tx.send(CaptureMsg::Stop).await;
+    });
+
+    let mut output = Vec::new();
+    while let Some(msg) = resampled_rx.recv().await {
+        match msg {
+            CaptureMsg::Audio(samples) => output.extend_from_slice(&samples),
+            _ => break,
+        }
+    }
+    // The resampler stops once the feeding task is done and its input is closed.
+    handle.await.map_err(AudioPipelineError::AudioPipelineTaskJoinError)??;
+    Ok(output)
+}
+
+/// Interleaved samples scaled to -1.0 - 1.0, with the sample rate and channel count of the file.
+fn read_wav(path: &Path) -> Result<(Vec<f32>, u32, u16), AudioPipelineError> {
+    let file_error = |e| AudioPipelineError::AudioFile(path.to_path_buf(), e);
+    let mut reader = hound::WavReader::open(path).map_err(file_error)?;
+    let spec = reader.spec();
+    let samples = match spec.sample_format {
+        hound::SampleFormat::Float => reader.samples::<f32>().collect::<Result<Vec<_>, _>>(),
+        hound::SampleFormat::Int => {
+            let scale = 1.0 / (1u64 << (spec.bits_per_sample - 1)) as f32;
+            reader.samples::<i32>().map(|s| s.map(|s| s as f32 * scale)).collect()
+        },
+    }.map_err(file_error)?;
+
+    Ok((samples, spec.sample_rate, spec.channels))
+}
@@ -0,0 +1,129 @@
+use std::fmt::Display;
+
+use lfs_stt::config::LogLevel;
+
+pub const USAGE: &str = "Usage: lfs_stt [--model <path>] [--list-devices] [--standalone] [--input-file <path>] [--log-level <level>] [-v | -vv] [--log-file <path>]
+
+Options:
+  --model <path>       Path to the whisper model file, overrides model_path from config.toml
+  --list-devices       Print available audio input devices and exit
+  --standalone         Run without LFS, press Enter to start/stop recording and print transcriptions
+  --input-file <path>  Transcribe a WAV file, or every WAV file in a directory, instead of the microphone
+  --log-level <level>  Logging verbosity (error, warn, info, debug, trace), overrides debug_log_level
+  -v, -vv              Shorthand for --log-level debug and --log-level trace
+  --log-file <path>    Append logs to this file instead of printing them
+
+RUST_LOG, when set, takes precedence over --log-level and -v.";
+
+#[derive(Debug)]
+pub enum CliError {
+    MissingValue(String),
+    InvalidValue(String, String),
+    UnknownArgument(String),
+}
+
+impl Display for CliError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CliError::MissingValue(arg) => write!(f, "Missing value for argument {}", arg),
+            CliError::InvalidValue(arg, value) => write!(f, "Invalid value {} for argument {}", value, arg),
+            CliError::UnknownArgument(arg) => write!(f, "Unknown argument {}", arg),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct CliArgs {
+    pub model_path: Option<String>,
+    pub list_devices: bool,
+    pub standalone: bool,
+    pub input_file: Option<String>,
+    pub log_level: Option<LogLevel>,
+    pub log_file: Option<String>,
+}
+
+impl CliArgs {
+    pub fn parse() -> Result<Self, CliError> {
+        Self::parse_from(std::env::args().skip(1))
+    }
+
+    pub fn parse_from(args: impl IntoIterator<Item = String>) -> Result<Self, CliError> {
+        let mut cli_args = CliArgs::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--model" => {
+                    let value = args.next().ok_or(CliError::MissingValue(arg))?;
+                    cli_args.model_path = Some(value);
+                },
+                "--list-devices" => cli_args.list_devices = true,
+                "--standalone" => cli_args.standalone = true,
+                "--input-file" => {
+                    let value = args.next().ok_or(CliError::MissingValue(arg))?;
+                    cli_args.input_file = Some(value);
+                },
+                "--log-level" => {
+                    let value = args.next().ok_or_else(|| CliError::MissingValue(arg.clone()))?;
+                    let level = value.parse().map_err(|_| CliError::InvalidValue(arg, value))?;
+                    cli_args.log_level = Some(level);
+                },
+                "-v" => cli_args.log_level = Some(match cli_args.log_level {
+                    Some(LogLevel::Debug | LogLevel::Trace) => LogLevel::Trace,
+                    _ => LogLevel::Debug,
+                }),
+                "-vv" => cli_args.log_level = Some(LogLevel::Trace),
+                "--log-file" => {
+                    let value = args.next().ok_or(CliError::MissingValue(arg))?;
+                    cli_args.log_file = Some(value);
+                },
+                _ => return Err(CliError::UnknownArgument(arg)),
+            }
+        }
+
+        Ok(cli_args)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<CliArgs, CliError> {
+        CliArgs::parse_from(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn parses_options() {
+        let args = parse(&["--model", "models/ggml-base.bin", "--standalone", "--log-file", "stt.log"]).unwrap();
+        assert_eq!(args.model_path.as_deref(), Some("models/ggml-base.bin"));
+        assert!(args.standalone);
+        assert!(!args.list_devices);
+        assert_eq!(args.log_file.as_deref(), Some("stt.log"));
+        assert!(args.log_level.is_none());
+    }
+
+    #[test]
+    fn missing_value_is_an_error() {
+        assert!(matches!(parse(&["--model"]), Err(CliError::MissingValue(arg)) if arg == "--model"));
+        assert!(matches!(parse(&["--log-level"]), Err(CliError::MissingValue(arg)) if arg == "--log-level"));
+    }
+
+    #[test]
+    fn unknown_argument_is_an_error() {
+        assert!(matches!(parse(&["--modle", "x"]), Err(CliError::UnknownArgument(arg)) if arg == "--modle"));
+    }
+
+    #[test]
+    fn invalid_log_level_is_an_error() {
+        assert!(matches!(parse(&["--log-level", "loud"]), Err(CliError::InvalidValue(_, value)) if value == "loud"));
+    }
+
+    #[test]
+    fn verbose_flags_escalate() {
+        assert!(matches!(parse(&["-v"]).unwrap().log_level, Some(LogLevel::Debug)));
+        assert!(matches!(parse(&["-v", "-v"]).unwrap().log_level, Some(LogLevel::Trace)));
+        assert!(matches!(parse(&["-vv"]).unwrap().log_level, Some(LogLevel::Trace)));
+        assert!(matches!(parse(&["--log-level", "warn", "-v"]).unwrap().log_level, Some(LogLevel::Debug)));
+    }
+}
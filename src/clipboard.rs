@@ -0,0 +1,24 @@
+use tracing::{debug, warn};
+
+/// Copies transcriptions to the system clipboard in standalone mode.
+/// Kept open for the whole session, on X11 the clipboard contents go away with it.
+pub struct ClipboardSink {
+    clipboard: Option<arboard::Clipboard>,
+}
+
+impl ClipboardSink {
+    pub fn new() -> Self {
+        let clipboard = arboard::Clipboard::new()
+            .map_err(|e| warn!("Failed to open the clipboard, transcriptions won't be copied: {}", e))
+            .ok();
+        ClipboardSink { clipboard }
+    }
+
+    pub fn copy(&mut self, text: &str) {
+        let Some(clipboard) = self.clipboard.as_mut() else { return; };
+        match clipboard.set_text(text) {
+            Ok(()) => debug!("Copied transcription to the clipboard"),
+            Err(e) => warn!("Failed to copy transcription to the clipboard: {}", e),
+        }
+    }
+}
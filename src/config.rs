@@ -1,8 +1,14 @@
-use std::fmt::Display;
+use std::{collections::HashMap, fmt::Display, path::Path};
 use serde::Deserialize;
 use tracing::level_filters::LevelFilter;
 
+use crate::models;
+
 pub const CONFIG_PATH: &str = "config.toml";
+/// Longest recording, whisper gets slow and unreliable well before this.
+const MAX_RECORDING_TIMEOUT_SECS: f32 = 300.0;
+/// Longest pre-roll or post-roll, more than this is no longer padding.
+const MAX_ROLL_MS: u64 = 2000;
 
 #[derive(Debug)]
 pub enum ConfigError {
@@ -32,6 +38,21 @@ pub enum LogLevel {
     Trace,
 }
 
+impl std::str::FromStr for LogLevel {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "error" => Ok(LogLevel::Error),
+            "warn" => Ok(LogLevel::Warn),
+            "info" => Ok(LogLevel::Info),
+            "debug" => Ok(LogLevel::Debug),
+            "trace" => Ok(LogLevel::Trace),
+            _ => Err(()),
+        }
+    }
+}
+
 impl From<LogLevel> for LevelFilter {
     fn from(level: LogLevel) -> LevelFilter {
         match level {
@@ -44,83 +65,647 @@ impl From<LogLevel> for LevelFilter {
     }
 }
 
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ResamplerQuality {
+    Fast,
+    Balanced,
+    #[default]
+    High,
+}
+
+/// How often a new file is started in `log_dir`.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogRotation {
+    Hourly,
+    #[default]
+    Daily,
+}
+
+/// Where `recording_announcement` is shown when recording starts.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AnnouncementTarget {
+    /// Only on the local screen, e.g. for stream viewers.
+    #[default]
+    Local,
+    /// Sent to the server chat on the selected channel.
+    Chat,
+}
+
+/// Game states reported by LFS in which recording is blocked, see `block_recording_in`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BlockingGameState {
+    Paused,
+    /// Shift+U free view mode.
+    ShiftU,
+    /// A dialog or menu is open on top of the game.
+    Dialog,
+}
+
+/// Letter case of transcriptions, applied after all other text processing.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TextCase {
+    #[default]
+    Preserve,
+    Lower,
+    Upper,
+    /// Capitalize the first letter of each sentence, the rest is left as whisper wrote it.
+    Sentence,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TranscriptFormat {
+    #[default]
+    Text,
+    Json,
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct Config {
+    #[serde(default)]
+    pub append_transcriptions: bool,
+    #[serde(default)]
+    pub auto_download: bool,
+    #[serde(default)]
+    pub auto_punctuate: bool,
+    #[serde(default)]
+    pub canned_messages: Vec<CannedMessage>,
+    #[serde(default = "default_beam_size")]
+    pub beam_size: u8,
+    #[serde(default)]
+    pub block_recording_in: Vec<BlockingGameState>,
+    #[serde(default = "default_best_of")]
+    pub best_of: u8,
     pub btn_id_offset: u8,
     pub debug_log_level: LogLevel,
     pub chat_channels: Vec<ChatChannel>,
+    #[serde(default = "default_chat_send_interval_ms")]
+    pub chat_send_interval_ms: u64,
+    #[serde(default)]
+    pub collect_segments: bool,
+    #[serde(default)]
+    pub copy_to_clipboard: bool,
     pub debug_audio_resampling: bool,
+    #[serde(default = "default_debug_wav_dir")]
+    pub debug_wav_dir: String,
+    #[serde(default = "default_fillers")]
+    pub fillers: Vec<String>,
+    #[serde(default)]
+    pub high_pass_filter: bool,
+    #[serde(default = "default_high_pass_cutoff_hz")]
+    pub high_pass_cutoff_hz: f32,
+    pub initial_prompt: Option<String>,
+    pub initial_prompt_path: Option<String>,
+    pub input_device: Option<String>,
+    #[serde(default = "default_fallback_confidence")]
+    pub fallback_confidence: f32,
+    pub fallback_model_path: Option<String>,
+    #[serde(default)]
+    pub feedback_tones: FeedbackTones,
+    #[serde(default = "default_in_game_debounce_ms")]
+    pub in_game_debounce_ms: u64,
+    #[serde(default)]
+    pub insim_commands: InsimCommands,
     pub insim_host: String,
+    #[serde(default = "default_insim_ping_interval_secs")]
+    pub insim_ping_interval_secs: u64,
     pub insim_port: String,
+    #[serde(default = "default_insim_timeout_secs")]
+    pub insim_timeout_secs: u64,
+    pub language: Option<String>,
+    pub log_dir: Option<String>,
+    #[serde(default)]
+    pub log_rotation: LogRotation,
+    #[serde(default = "default_alternatives_confidence")]
+    pub alternatives_confidence: f32,
+    #[serde(default = "default_max_alternatives")]
+    pub max_alternatives: u8,
+    #[serde(default = "default_max_chat_messages")]
+    pub max_chat_messages: usize,
+    #[serde(default = "default_max_log_files")]
+    pub max_log_files: usize,
+    #[serde(default = "default_max_transcription_errors")]
+    pub max_transcription_errors: u8,
     pub message_preview_timeout_secs: u64,
+    #[serde(default)]
+    pub min_confidence: f32,
+    #[serde(default = "default_min_recording_ms")]
+    pub min_recording_ms: u64,
     pub model_path: String,
-    pub recording_timeout_secs: u8,
+    pub n_threads: Option<usize>,
+    #[serde(default)]
+    pub noise_gate: bool,
+    #[serde(default = "default_noise_gate_attack_ms")]
+    pub noise_gate_attack_ms: f32,
+    #[serde(default = "default_noise_gate_release_ms")]
+    pub noise_gate_release_ms: f32,
+    #[serde(default = "default_noise_gate_threshold_db")]
+    pub noise_gate_threshold_db: f32,
+    #[serde(default)]
+    pub normalize_audio: bool,
+    #[serde(default = "default_normalize_target_peak")]
+    pub normalize_target_peak: f32,
+    #[serde(default)]
+    pub post_roll_ms: u64,
+    #[serde(default = "default_pre_roll_ms")]
+    pub pre_roll_ms: u64,
+    #[serde(default)]
+    pub profanity_filter: bool,
+    #[serde(default)]
+    pub profanity_words: Vec<String>,
+    pub profanity_words_path: Option<String>,
+    pub recording_announcement: Option<String>,
+    #[serde(default)]
+    pub recording_announcement_target: AnnouncementTarget,
+    pub recording_timeout_secs: f32,
+    #[serde(default)]
+    pub resampler_quality: ResamplerQuality,
+    #[serde(default)]
+    pub show_control_buttons: bool,
+    #[serde(default)]
+    pub show_channel_in_preview: bool,
+    #[serde(default)]
+    pub show_latency: bool,
+    #[serde(default = "default_true")]
+    pub show_no_speech_hint: bool,
+    #[serde(default = "default_silence_threshold")]
+    pub silence_threshold: f32,
+    #[serde(default = "default_transcription_queue_size")]
+    pub transcription_queue_size: usize,
+    #[serde(default)]
+    pub transcript_format: TranscriptFormat,
+    pub transcript_path: Option<String>,
+    #[serde(default)]
+    pub translate: bool,
+    #[serde(default)]
+    pub remove_fillers: bool,
+    #[serde(default)]
+    pub replacements: HashMap<String, String>,
+    #[serde(default = "default_true")]
+    pub strip_non_speech: bool,
+    #[serde(default)]
+    pub stats_log_interval: u64,
+    #[serde(default)]
+    pub text_case: TextCase,
+    #[serde(default = "default_sampling")]
+    pub sampling: String,
+    #[serde(default)]
+    pub ui_colors: UiColors,
     pub ui_offset_left: u8,
     pub ui_offset_top: u8,
     pub ui_scale: u8,
     pub use_gpu: bool,
+    #[serde(default)]
+    pub voice_commands: HashMap<String, String>,
+    #[serde(default)]
+    pub words_to_digits: bool,
+}
+
+fn default_true() -> bool { true }
+fn default_beam_size() -> u8 { 5 }
+fn default_best_of() -> u8 { 8 }
+fn default_sampling() -> String { "greedy".into() }
+fn default_in_game_debounce_ms() -> u64 { 500 }
+fn default_insim_ping_interval_secs() -> u64 { 10 }
+fn default_insim_timeout_secs() -> u64 { 30 }
+fn default_debug_wav_dir() -> String { "debug".into() }
+fn default_chat_send_interval_ms() -> u64 { 200 }
+fn default_high_pass_cutoff_hz() -> f32 { 80.0 }
+fn default_normalize_target_peak() -> f32 { 0.9 }
+fn default_silence_threshold() -> f32 { 0.001 }
+fn default_noise_gate_threshold_db() -> f32 { 10.0 }
+fn default_noise_gate_attack_ms() -> f32 { 5.0 }
+fn default_noise_gate_release_ms() -> f32 { 150.0 }
+fn default_pre_roll_ms() -> u64 { 300 }
+fn default_min_recording_ms() -> u64 { 300 }
+fn default_fallback_confidence() -> f32 { 0.6 }
+fn default_fillers() -> Vec<String> { vec!["um".into(), "uh".into(), "er".into()] }
+fn default_max_alternatives() -> u8 { 1 }
+fn default_alternatives_confidence() -> f32 { 0.8 }
+fn default_transcription_queue_size() -> usize { 3 }
+fn default_max_chat_messages() -> usize { 3 }
+fn default_max_log_files() -> usize { 7 }
+fn default_max_transcription_errors() -> u8 { 3 }
+
+/// Short tones played when recording starts and when a transcription is ready.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct FeedbackTones {
+    pub done_frequency_hz: f32,
+    pub duration_ms: u64,
+    pub enabled: bool,
+    pub start_frequency_hz: f32,
+    pub volume: f32,
+}
+
+impl Default for FeedbackTones {
+    fn default() -> Self {
+        FeedbackTones {
+            done_frequency_hz: 660.0,
+            duration_ms: 120,
+            enabled: false,
+            start_frequency_hz: 880.0,
+            volume: 0.2,
+        }
+    }
+}
+
+/// Chat commands (typed as `/o <command>`) bound to each action, multiple aliases are allowed.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct InsimCommands {
+    pub accept_message: Vec<String>,
+    pub cancel_recording: Vec<String>,
+    pub clear_message: Vec<String>,
+    pub disable: Vec<String>,
+    pub drop_last_word: Vec<String>,
+    pub enable: Vec<String>,
+    pub keep_message: Vec<String>,
+    /// Followed by a player name, e.g. `stt to Bob`.
+    pub message_target: Vec<String>,
+    pub next_alternative: Vec<String>,
+    pub next_channel: Vec<String>,
+    pub previous_channel: Vec<String>,
+    pub reload_model: Vec<String>,
+    pub repeat_last: Vec<String>,
+    pub toggle_recording: Vec<String>,
+}
+
+impl Default for InsimCommands {
+    fn default() -> Self {
+        InsimCommands {
+            accept_message: vec!["stt accept".into()],
+            cancel_recording: vec!["stt cancel".into()],
+            clear_message: vec!["stt clear".into()],
+            disable: vec!["stt off".into()],
+            drop_last_word: vec!["stt undo".into()],
+            enable: vec!["stt on".into()],
+            keep_message: vec!["stt keep".into()],
+            message_target: vec!["stt to".into()],
+            next_alternative: vec!["stt alt".into()],
+            next_channel: vec!["stt nc".into()],
+            previous_channel: vec!["stt pc".into()],
+            reload_model: vec!["stt reload".into()],
+            repeat_last: vec!["stt again".into()],
+            toggle_recording: vec!["stt talk".into()],
+        }
+    }
+}
+
+impl InsimCommands {
+    pub fn all(&self) -> impl Iterator<Item = &String> {
+        self.accept_message.iter()
+            .chain(&self.cancel_recording)
+            .chain(&self.clear_message)
+            .chain(&self.disable)
+            .chain(&self.drop_last_word)
+            .chain(&self.enable)
+            .chain(&self.keep_message)
+            .chain(&self.message_target)
+            .chain(&self.next_alternative)
+            .chain(&self.next_channel)
+            .chain(&self.previous_channel)
+            .chain(&self.reload_model)
+            .chain(&self.repeat_last)
+            .chain(&self.toggle_recording)
+    }
+}
+
+/// LFS colour codes (0-9) used for the state indicator and the message preview.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct UiColors {
+    pub disabled: u8,
+    pub glyph: String,
+    pub idle: u8,
+    pub preview: u8,
+    /// Preview colour while a channel without a recipient is selected, `preview` when unset.
+    pub preview_public: Option<u8>,
+    /// Preview colour while a private channel is selected, `preview` when unset.
+    pub preview_private: Option<u8>,
+    pub processing: u8,
+    pub recording: u8,
+}
+
+impl Default for UiColors {
+    fn default() -> Self {
+        UiColors {
+            disabled: 9,
+            glyph: "•".into(),
+            idle: 2,
+            preview: 3,
+            preview_public: None,
+            preview_private: None,
+            processing: 3,
+            recording: 1,
+        }
+    }
+}
+
+/// Preset chat message, sent by command or spoken phrase without going through the preview.
+#[derive(Debug, Clone, Deserialize)]
+pub struct CannedMessage {
+    pub text: String,
+    #[serde(default)]
+    pub commands: Vec<String>,
+    #[serde(default)]
+    pub phrases: Vec<String>,
 }
 
 #[derive(Clone, Debug, serde::Deserialize)]
 pub struct ChatChannel {
     pub display: String,
     pub prefix: String,
+    /// Recipient of a private channel, sent after the prefix. Present but empty until
+    /// it is set in game with the `message_target` command.
+    #[serde(default)]
+    pub target: Option<String>,
 }
 
-impl PartialEq for ChatChannel {
-    fn eq(&self, other: &Self) -> bool {
-        self.prefix == other.prefix
+impl Default for ChatChannel {
+    fn default() -> Self {
+        ChatChannel {
+            display: "/say".into(),
+            prefix: "".into(),
+            target: None,
+        }
     }
 }
 
+impl ChatChannel {
+    /// Prefix of outgoing messages, including the recipient of a private channel.
+    pub fn outgoing_prefix(&self) -> String {
+        match self.target.as_deref() {
+            Some(target) if !target.is_empty() => format!("{} {}", self.prefix, target),
+            _ => self.prefix.clone(),
+        }
+    }
+
+    /// Text shown in the UI, including the recipient of a private channel.
+    pub fn label(&self) -> String {
+        match self.target.as_deref() {
+            Some(target) if !target.is_empty() => format!("{} {}", self.display, target),
+            _ => self.display.clone(),
+        }
+    }
+}
 
-impl Display for Config {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "Config {{ insim_host: {}, insim_port: {}, chat_channels: {:?}, model_path: {}, message_preview_timeout_secs: {}, recording_timeout_secs: {}, ui_scale: {}, ui_offset_top: {}, ui_offset_left: {}, btn_id_offset: {}, debug_audio_resampling: {}, use_gpu: {} }}",
-            self.insim_host, self.insim_port, self.chat_channels, self.model_path, self.message_preview_timeout_secs, self.recording_timeout_secs, self.ui_scale, self.ui_offset_top, self.ui_offset_left, self.btn_id_offset, self.debug_audio_resampling, self.use_gpu)
+impl PartialEq for ChatChannel {
+    fn eq(&self, other: &Self) -> bool {
+        self.prefix == other.prefix
     }
 }
 
+
 impl Config {
     pub fn load() -> Result<Self, ConfigError> {
         let contents = std::fs::read_to_string(CONFIG_PATH)
             .map_err(ConfigError::Io)?;
-        let config: Self = toml::from_str(&contents)
+        let mut config: Self = toml::from_str(&contents)
             .map_err(ConfigError::Parse)?;
-        config.validate()?;
+        if config.chat_channels.is_empty() {
+            config.chat_channels.push(ChatChannel::default());
+        }
 
         Ok(config)
     }
 
-    pub fn validate(&self) -> Result<(), ConfigError> {
+    /// Settings that work but not as intended with the model at `model_path`. Whisper falls back
+    /// to English without translating for English-only models.
+    pub fn model_warnings(&self, model_path: &str) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if models::is_english_only_model(model_path) {
+            if let Some(language) = self.language.as_deref()
+                && language != "en"
+            {
+                warnings.push(format!("Language is set to \"{}\" but {} is an English-only model, it will transcribe English. Use a multilingual model instead.", language, model_path));
+            }
+            if self.translate {
+                warnings.push(format!("Translate is enabled but {} is an English-only model, it can't translate. Use a multilingual model (one without \".en\" in the name).", model_path));
+            }
+        }
+        warnings
+    }
+
+    /// Check the whole config up front, reporting every problem instead of stopping at the first.
+    /// `model_path` is the model that will be loaded, `--model` can override the one in the config.
+    pub fn validate(&self, model_path: &str) -> Result<(), Vec<ConfigError>> {
+        let mut errors = Vec::new();
         if self.chat_channels.is_empty() {
-            return Err(ConfigError::ValidationError("Result<(), String>".into()));
+            errors.push(ConfigError::ValidationError("At least one chat channel must be defined.".into()));
         }
 
         if self.ui_scale == 0 {
-            return Err(ConfigError::ValidationError("UI scale must be greater than 0.".into()))
+            errors.push(ConfigError::ValidationError("UI scale must be greater than 0.".into()));
         }
 
-        if self.ui_offset_top > 200 {
-            return Err(ConfigError::ValidationError("UI offset top must be between 0 and 200.".into()))
+        if !(self.recording_timeout_secs > 0.0 && self.recording_timeout_secs <= MAX_RECORDING_TIMEOUT_SECS) {
+            errors.push(ConfigError::ValidationError(format!("Recording timeout must be greater than 0 and at most {} seconds.", MAX_RECORDING_TIMEOUT_SECS)));
         }
 
-        if self.ui_offset_left > 200 {
-            return Err(ConfigError::ValidationError("UI offset left must be between 0 and 200.".into()))
-        }
-        if self.model_path.is_empty() {
-            return Err(ConfigError::ValidationError("Model path cannot be empty.".into()))
+        if model_path.is_empty() {
+            errors.push(ConfigError::ValidationError("Model path cannot be empty.".into()));
+        } else if !self.auto_download && !Path::new(model_path).is_file() {
+            errors.push(ConfigError::ValidationError(format!("Model file {} does not exist, download it or enable auto_download.", model_path)));
         }
         if self.btn_id_offset > 230 {
-            return Err(ConfigError::ValidationError("Button ID offset must be between 0 and 230.".into()))
+            errors.push(ConfigError::ValidationError("Button ID offset must be between 0 and 230.".into()));
+        }
+        if self.best_of == 0 {
+            errors.push(ConfigError::ValidationError("Best of must be greater than 0.".into()));
+        }
+        if !(1..=5).contains(&self.max_alternatives) {
+            errors.push(ConfigError::ValidationError("Max alternatives must be between 1 and 5.".into()));
+        }
+        if self.max_chat_messages == 0 {
+            errors.push(ConfigError::ValidationError("Max chat messages must be greater than 0.".into()));
+        }
+        if self.insim_ping_interval_secs > 0 && self.insim_timeout_secs <= self.insim_ping_interval_secs {
+            errors.push(ConfigError::ValidationError("InSim timeout must be longer than the ping interval.".into()));
+        }
+        if self.log_dir.is_some() && self.max_log_files == 0 {
+            errors.push(ConfigError::ValidationError("Max log files must be greater than 0.".into()));
+        }
+        if self.use_gpu && !cfg!(feature = "gpu") {
+            errors.push(ConfigError::ValidationError("use_gpu is enabled but this build has no GPU support (built with cpu-only), set use_gpu = false.".into()));
+        }
+        if self.copy_to_clipboard && !cfg!(feature = "clipboard") {
+            errors.push(ConfigError::ValidationError("copy_to_clipboard is enabled but this build has no clipboard support (build with --features clipboard).".into()));
+        }
+        if self.transcription_queue_size == 0 {
+            errors.push(ConfigError::ValidationError("Transcription queue size must be greater than 0.".into()));
+        }
+        if self.max_transcription_errors == 0 {
+            errors.push(ConfigError::ValidationError("Max transcription errors must be greater than 0.".into()));
+        }
+        if !(0.0..=1.0).contains(&self.min_confidence) {
+            errors.push(ConfigError::ValidationError("Min confidence must be between 0.0 and 1.0.".into()));
+        }
+        if !(0.0..=1.0).contains(&self.alternatives_confidence) {
+            errors.push(ConfigError::ValidationError("Alternatives confidence must be between 0.0 and 1.0.".into()));
+        }
+        if !(0.0..=1.0).contains(&self.fallback_confidence) {
+            errors.push(ConfigError::ValidationError("Fallback confidence must be between 0.0 and 1.0.".into()));
+        }
+        if let Some(path) = &self.fallback_model_path
+            && !Path::new(path).is_file()
+        {
+            errors.push(ConfigError::ValidationError(format!("Fallback model file {} does not exist.", path)));
+        }
+        if self.normalize_target_peak <= 0.0 || self.normalize_target_peak > 1.0 {
+            errors.push(ConfigError::ValidationError("Normalize target peak must be between 0.0 and 1.0.".into()));
+        }
+        if self.high_pass_cutoff_hz <= 0.0 || self.high_pass_cutoff_hz >= 8000.0 {
+            errors.push(ConfigError::ValidationError("High-pass cutoff must be between 0 and 8000 Hz.".into()));
+        }
+        if self.n_threads == Some(0) {
+            errors.push(ConfigError::ValidationError("Number of threads must be at least 1.".into()));
+        }
+        if !(0.0..1.0).contains(&self.silence_threshold) {
+            errors.push(ConfigError::ValidationError("Silence threshold must be between 0.0 and 1.0.".into()));
+        }
+        if self.noise_gate_threshold_db < 0.0 {
+            errors.push(ConfigError::ValidationError("Noise gate threshold must be at least 0 dB.".into()));
+        }
+        if self.noise_gate_attack_ms <= 0.0 || self.noise_gate_release_ms <= 0.0 {
+            errors.push(ConfigError::ValidationError("Noise gate attack and release must be greater than 0 ms.".into()));
+        }
+        if self.pre_roll_ms > MAX_ROLL_MS || self.post_roll_ms > MAX_ROLL_MS {
+            errors.push(ConfigError::ValidationError(format!("Pre-roll and post-roll must be at most {} ms.", MAX_ROLL_MS)));
+        }
+        if self.beam_size == 0 {
+            errors.push(ConfigError::ValidationError("Beam size must be greater than 0.".into()));
+        }
+
+        if self.feedback_tones.enabled {
+            let tones = &self.feedback_tones;
+            if tones.start_frequency_hz <= 0.0 || tones.done_frequency_hz <= 0.0 {
+                errors.push(ConfigError::ValidationError("Feedback tone frequencies must be greater than 0.".into()));
+            }
+            if !(0.0..=1.0).contains(&tones.volume) {
+                errors.push(ConfigError::ValidationError("Feedback tone volume must be between 0.0 and 1.0.".into()));
+            }
+        }
+
+        let mut commands = std::collections::HashSet::new();
+        let canned_commands = self.canned_messages.iter().flat_map(|canned| &canned.commands);
+        for command in self.insim_commands.all().chain(canned_commands) {
+            if command.trim().is_empty() {
+                errors.push(ConfigError::ValidationError("InSim commands cannot be empty.".into()));
+                continue;
+            }
+            if !commands.insert(command.trim()) {
+                errors.push(ConfigError::ValidationError(format!("InSim command \"{}\" is bound to more than one action.", command.trim())));
+            }
+        }
+
+        for canned in &self.canned_messages {
+            if canned.text.trim().is_empty() {
+                errors.push(ConfigError::ValidationError("Canned message text cannot be empty.".into()));
+            }
+            if canned.commands.is_empty() && canned.phrases.is_empty() {
+                errors.push(ConfigError::ValidationError(format!("Canned message \"{}\" needs at least one command or phrase.", canned.text.trim())));
+            }
+        }
+
+        for (phrase, command) in &self.voice_commands {
+            if phrase.trim().is_empty() || command.trim().is_empty() {
+                errors.push(ConfigError::ValidationError("Voice command phrases and commands cannot be empty.".into()));
+            }
         }
 
         for channel in &self.chat_channels {
             if channel.display.is_empty() {
-                return Err(ConfigError::ValidationError("Chat channel display name cannot be empty.".into()))
+                errors.push(ConfigError::ValidationError("Chat channel display name cannot be empty.".into()));
             }
         }
 
-        Ok(())
+        if errors.is_empty() { Ok(()) } else { Err(errors) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINIMAL: &str = r#"
+        btn_id_offset = 10
+        debug_log_level = "info"
+        debug_audio_resampling = false
+        insim_host = "127.0.0.1"
+        insim_port = "29999"
+        message_preview_timeout_secs = 10
+        model_path = "models/ggml-base.en.bin"
+        auto_download = true
+        recording_timeout_secs = 30.0
+        ui_offset_left = 10
+        ui_offset_top = 170
+        ui_scale = 5
+        use_gpu = false
+
+        [[chat_channels]]
+        display = "All"
+        prefix = "/say"
+    "#;
+
+    fn config() -> Config {
+        toml::from_str(MINIMAL).unwrap()
+    }
+
+    fn messages(errors: Vec<ConfigError>) -> Vec<String> {
+        errors.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn minimal_config_is_valid() {
+        let config = config();
+        assert!(config.validate(&config.model_path).is_ok());
+    }
+
+    #[test]
+    fn reports_every_problem() {
+        let mut config = config();
+        config.ui_scale = 0;
+        config.btn_id_offset = 240;
+        config.best_of = 0;
+        config.max_alternatives = 9;
+        config.recording_timeout_secs = 0.0;
+        config.min_confidence = 2.0;
+        config.chat_channels[0].display.clear();
+
+        let errors = messages(config.validate(&config.model_path).unwrap_err());
+        for expected in ["UI scale", "Button ID offset", "Best of", "Max alternatives", "Recording timeout", "Min confidence", "Chat channel display name"] {
+            assert!(errors.iter().any(|e| e.contains(expected)), "no error about {}: {:?}", expected, errors);
+        }
+        assert_eq!(errors.len(), 7, "{:?}", errors);
+    }
+
+    #[test]
+    fn reports_duplicate_and_empty_commands() {
+        let mut config = config();
+        config.insim_commands.accept_message = vec!["stt go".into()];
+        config.insim_commands.keep_message = vec![" stt go ".into(), "".into()];
+
+        let errors = messages(config.validate(&config.model_path).unwrap_err());
+        assert_eq!(errors.len(), 2, "{:?}", errors);
+        assert!(errors.iter().any(|e| e.contains("\"stt go\" is bound to more than one action")));
+        assert!(errors.iter().any(|e| e.contains("cannot be empty")));
+    }
+
+    #[test]
+    fn english_only_model_warns_about_other_languages() {
+        let mut config = config();
+        config.language = Some("de".into());
+        config.translate = true;
+
+        assert!(config.validate("models/ggml-base.en.bin").is_ok());
+        let warnings = config.model_warnings("models/ggml-base.en.bin");
+        assert_eq!(warnings.len(), 2, "{:?}", warnings);
+        assert!(config.model_warnings("models/ggml-base.bin").is_empty());
     }
 }
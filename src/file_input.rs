@@ -0,0 +1,62 @@
+use std::{path::{Path, PathBuf}, sync::Arc};
+use anyhow::Context;
+
+use lfs_stt::audio::{speech_to_text::{SpeechToText, SttMessageType}, wav_input};
+
+use crate::{global::CONFIG, stats::SessionStats};
+
+/// Transcribe WAV files instead of the microphone, for reproducible tests and comparing models.
+/// `path` is a file or a directory, whose `.wav` files are transcribed in name order.
+/// Each result is printed to stdout as `<file>\t<transcription>`.
+pub async fn run(path: &str, model_path: String) -> anyhow::Result<()> {
+    let files = collect_files(Path::new(path))?;
+    if files.is_empty() {
+        anyhow::bail!("No .wav files found in {}", path);
+    }
+
+    let mut stt = SpeechToText::new(model_path, Arc::clone(&CONFIG))
+        .await
+        .context("Failed to initialize speech-to-text")?;
+    let mut stats = SessionStats::default();
+
+    for file in &files {
+        let samples = wav_input::load(file, &CONFIG)
+            .await
+            .with_context(|| format!("Failed to read {}", file.display()))?;
+        if !stt.submit(samples).await {
+            anyhow::bail!("Speech-to-text stopped unexpectedly, check the log");
+        }
+        let msg = stt.results().recv()
+            .await
+            .context("Speech-to-text stopped unexpectedly, check the log")?;
+
+        match msg.msg_type {
+            SttMessageType::TranscriptionResult => {
+                stats.record(&msg.content, msg.audio_ms, msg.processing_ms);
+                println!("{}\t{}", file.display(), msg.content);
+            },
+            SttMessageType::NoSpeech => {
+                eprintln!("{}: no speech detected", file.display());
+                println!("{}\t", file.display());
+            },
+            _ => eprintln!("{}: {}", file.display(), msg),
+        }
+    }
+
+    stats.log_summary();
+    Ok(())
+}
+
+fn collect_files(path: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    if !path.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+
+    let mut files = std::fs::read_dir(path)
+        .with_context(|| format!("Failed to read directory {}", path.display()))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|file| file.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("wav")))
+        .collect::<Vec<_>>();
+    files.sort();
+    Ok(files)
+}
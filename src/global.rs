@@ -1,7 +1,24 @@
-use crate::config::Config;
+use lfs_stt::config::Config;
+
+use crate::cli::{CliArgs, USAGE};
 use once_cell::sync::Lazy;
 use std::sync::Arc;
 
+pub static ARGS: Lazy<CliArgs> = Lazy::new(|| {
+    match CliArgs::parse() {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            std::process::exit(2);
+        }
+    }
+});
+
+/// Model file to use, `--model` from the command line wins over `model_path` in config.
+pub fn model_path() -> String {
+    ARGS.model_path.clone().unwrap_or_else(|| CONFIG.model_path.clone())
+}
+
 pub static CONFIG: Lazy<Arc<Config>> = Lazy::new(|| {
     let cfg = match Config::load().map_err(|e| {
         eprintln!("Failed to load config: {}", e);
@@ -10,7 +27,5 @@ pub static CONFIG: Lazy<Arc<Config>> = Lazy::new(|| {
         Ok(cfg) => cfg,
         Err(_) => { panic!(); }
     };
-    cfg.validate().expect("Invalid config.toml");
     Arc::new(cfg)
 });
-
@@ -1,26 +1,110 @@
-use insim::builder::InsimTask;
-use tokio::{sync::mpsc::Receiver, task::JoinHandle};
-use tracing::{info};
+use std::{collections::HashMap, time::Duration};
+use insim::{builder::InsimTask, identifiers::ClickId, insim::StaFlags};
+use once_cell::sync::Lazy;
+use tokio::{sync::{broadcast::error::RecvError, mpsc::Receiver}, task::{AbortHandle, JoinHandle}, time::Instant};
+use tracing::{info, warn};
 
-use crate::global::CONFIG;
+use lfs_stt::config::{BlockingGameState, CannedMessage, InsimCommands};
+
+use crate::{global::CONFIG, ui};
 
 #[allow(dead_code)]
+#[derive(Debug, Clone)]
 pub enum InsimEvent {
     ToggleRecording,
     AcceptMessage,
     NextChannel,
     PeviousChannel,
+    DropLastWord,
+    CancelRecording,
+    ClearMessage,
+    KeepMessage,
+    NextAlternative,
+    RepeatLast,
+    ReloadModel,
+    /// Index into `canned_messages` in config.
+    SendCanned(usize),
+    /// Select the private channel and send messages to this player.
+    SetMessageTarget(String),
+    SetEnabled(bool),
     IsInGame(bool),
+    /// The game is in one of the `block_recording_in` states.
+    IsBlocked(bool),
+}
+
+/// Command string -> event lookup, built from `insim_commands` in config.
+static COMMANDS: Lazy<HashMap<String, InsimEvent>> = Lazy::new(|| {
+    command_map(&CONFIG.insim_commands, &CONFIG.canned_messages)
+});
+
+fn command_map(commands: &InsimCommands, canned_messages: &[CannedMessage]) -> HashMap<String, InsimEvent> {
+    let bindings = [
+        (&commands.toggle_recording, InsimEvent::ToggleRecording),
+        (&commands.accept_message, InsimEvent::AcceptMessage),
+        (&commands.next_channel, InsimEvent::NextChannel),
+        (&commands.previous_channel, InsimEvent::PeviousChannel),
+        (&commands.drop_last_word, InsimEvent::DropLastWord),
+        (&commands.cancel_recording, InsimEvent::CancelRecording),
+        (&commands.clear_message, InsimEvent::ClearMessage),
+        (&commands.keep_message, InsimEvent::KeepMessage),
+        (&commands.next_alternative, InsimEvent::NextAlternative),
+        (&commands.repeat_last, InsimEvent::RepeatLast),
+        (&commands.reload_model, InsimEvent::ReloadModel),
+        (&commands.enable, InsimEvent::SetEnabled(true)),
+        (&commands.disable, InsimEvent::SetEnabled(false)),
+    ];
+
+    let mut map = HashMap::new();
+    for (aliases, event) in bindings {
+        for alias in aliases {
+            map.insert(alias.trim().to_string(), event.clone());
+        }
+    }
+    for (index, canned) in canned_messages.iter().enumerate() {
+        for alias in &canned.commands {
+            map.insert(alias.trim().to_string(), InsimEvent::SendCanned(index));
+        }
+    }
+    map
+}
+
+/// Event for a command string, exact matches from `commands` first, then commands that take
+/// an argument.
+fn parse_command(cmd: &str, commands: &HashMap<String, InsimEvent>, message_target: &[String]) -> Option<InsimEvent> {
+    let cmd = cmd.trim();
+    if let Some(event) = commands.get(cmd) {
+        return Some(event.clone());
+    }
+
+    // Commands with an argument, matched by prefix.
+    message_target.iter()
+        .filter_map(|alias| cmd.strip_prefix(alias.trim())?.strip_prefix(' '))
+        .map(str::trim)
+        .find(|target| !target.is_empty())
+        .map(|target| InsimEvent::SetMessageTarget(target.to_string()))
 }
 
 impl InsimEvent {
     pub fn from_string(cmd: String) -> Option<InsimEvent> {
-        match cmd.as_str() {
-            "stt talk" => Some(InsimEvent::ToggleRecording),
-            "stt accept" => Some(InsimEvent::AcceptMessage),
-            "stt nc" => Some(InsimEvent::NextChannel),
-            "stt pc" => Some(InsimEvent::PeviousChannel),
-            _ => None,
+        parse_command(&cmd, &COMMANDS, &CONFIG.insim_commands.message_target)
+    }
+
+    /// Whether the game state blocks recording, per `block_recording_in` in config.
+    fn is_blocked(flags: StaFlags) -> bool {
+        CONFIG.block_recording_in.iter().any(|state| match state {
+            BlockingGameState::Paused => flags.contains(StaFlags::PAUSED),
+            BlockingGameState::ShiftU => flags.contains(StaFlags::SHIFTU),
+            BlockingGameState::Dialog => flags.contains(StaFlags::DIALOG),
+        })
+    }
+
+    pub fn from_click(clickid: ClickId) -> Option<InsimEvent> {
+        if clickid == ClickId::from(CONFIG.btn_id_offset + ui::TALK_BTN_ID) {
+            Some(InsimEvent::ToggleRecording)
+        } else if clickid == ClickId::from(CONFIG.btn_id_offset + ui::ACCEPT_BTN_ID) {
+            Some(InsimEvent::AcceptMessage)
+        } else {
+            None
         }
     }
 }
@@ -42,23 +126,38 @@ pub async fn init_insim() -> Result<(InsimTask, Receiver<InsimEvent>, JoinHandle
         }
     };
     info!("Connected to INSIM.");
+    spawn_keepalive(insim.clone(), handle.abort_handle());
 
     let mut rx = insim.subscribe();
     tokio::spawn(async move {
         loop {
-            while let Ok(packet) = rx.recv().await {
-                match packet {
-                    insim::Packet::Mso(mso) => {
-                        if let Some(cmd) = InsimEvent::from_string(mso.msg) {
-                            let _ = event_tx.send(cmd).await;
-                        }
-                    },
-                    insim::Packet::Sta(sta) => {
-                        let _ = event_tx.send(InsimEvent::IsInGame(sta.flags.is_in_game())).await;
+            let packet = match rx.recv().await {
+                Ok(packet) => packet,
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!("Insim event reader lagged behind, skipped {} packets", skipped);
+                    continue;
+                },
+                // Connection is gone, the insim task handle reports why.
+                Err(RecvError::Closed) => break,
+            };
+
+            match packet {
+                insim::Packet::Mso(mso) => {
+                    if let Some(cmd) = InsimEvent::from_string(mso.msg) {
+                        let _ = event_tx.send(cmd).await;
                     }
-                    _ => {}
-                };
-            }
+                },
+                insim::Packet::Btc(btc) => {
+                    if let Some(event) = InsimEvent::from_click(btc.clickid) {
+                        let _ = event_tx.send(event).await;
+                    }
+                },
+                insim::Packet::Sta(sta) => {
+                    let _ = event_tx.send(InsimEvent::IsInGame(sta.flags.is_in_game())).await;
+                    let _ = event_tx.send(InsimEvent::IsBlocked(InsimEvent::is_blocked(sta.flags))).await;
+                }
+                _ => {}
+            };
         }
     });
 
@@ -70,3 +169,79 @@ pub async fn init_insim() -> Result<(InsimTask, Receiver<InsimEvent>, JoinHandle
 
     Ok((insim, event_rx, handle))
 }
+
+/// Ping LFS every `insim_ping_interval_secs` and abort the connection task when nothing arrives
+/// for `insim_timeout_secs`, so a half-open connection gets noticed and reconnected.
+fn spawn_keepalive(insim: InsimTask, connection: AbortHandle) {
+    if CONFIG.insim_ping_interval_secs == 0 {
+        return;
+    }
+
+    let mut rx = insim.subscribe();
+    tokio::spawn(async move {
+        let timeout = Duration::from_secs(CONFIG.insim_timeout_secs);
+        let mut ping_timer = tokio::time::interval(Duration::from_secs(CONFIG.insim_ping_interval_secs));
+        let deadline = tokio::time::sleep(timeout);
+        tokio::pin!(deadline);
+
+        loop {
+            tokio::select! {
+                _ = ping_timer.tick() => {
+                    let ping = insim::Packet::Tiny(insim::insim::Tiny{
+                        subt: insim::insim::TinyType::Ping,
+                        reqi: insim::identifiers::RequestId::from(2),
+                    });
+                    if insim.send(ping).await.is_err() {
+                        break;
+                    }
+                },
+                packet = rx.recv() => match packet {
+                    // Any packet, including the ping reply, proves the connection is alive.
+                    Ok(_) | Err(RecvError::Lagged(_)) => deadline.as_mut().reset(Instant::now() + timeout),
+                    Err(RecvError::Closed) => break,
+                },
+                _ = &mut deadline => {
+                    warn!("No InSim packets received for {}s, dropping the connection", CONFIG.insim_timeout_secs);
+                    connection.abort();
+                    break;
+                },
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(cmd: &str) -> Option<InsimEvent> {
+        let canned = [CannedMessage {
+            text: "Sorry!".to_string(),
+            commands: vec!["stt sorry".to_string()],
+            phrases: Vec::new(),
+        }];
+        let commands = InsimCommands::default();
+        parse_command(cmd, &command_map(&commands, &canned), &commands.message_target)
+    }
+
+    #[test]
+    fn matches_configured_commands() {
+        assert!(matches!(parse("stt accept"), Some(InsimEvent::AcceptMessage)));
+        assert!(matches!(parse("  stt off "), Some(InsimEvent::SetEnabled(false))));
+        assert!(matches!(parse("stt sorry"), Some(InsimEvent::SendCanned(0))));
+    }
+
+    #[test]
+    fn command_inside_longer_text_does_not_match() {
+        assert!(parse("stt accept now").is_none());
+        assert!(parse("please stt accept").is_none());
+        assert!(parse("stt").is_none());
+    }
+
+    #[test]
+    fn message_target_takes_the_rest_as_argument() {
+        assert!(matches!(parse("stt to  Bob Smith "), Some(InsimEvent::SetMessageTarget(name)) if name == "Bob Smith"));
+        assert!(parse("stt to").is_none());
+        assert!(parse("stt tomorrow").is_none());
+    }
+}
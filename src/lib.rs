@@ -0,0 +1,16 @@
+//! Speech-to-text for Live for Speed: audio capture, whisper transcription and text cleanup.
+//! The `lfs_stt` binary adds the InSim UI on top. Other programs can transcribe recordings with
+//! `SpeechToText::new`, which only uses the `Config` it is given and never reads `config.toml`
+//! or the command line.
+
+#[cfg(all(feature = "gpu", feature = "cpu-only"))]
+compile_error!("features \"gpu\" and \"cpu-only\" can't be enabled together, build with --no-default-features --features cpu-only");
+
+pub mod audio;
+pub mod config;
+pub mod models;
+pub mod text;
+pub mod transcripts;
+
+pub use audio::speech_to_text::{SpeechToText, SttMessage, SttMessageType};
+pub use config::Config;
@@ -1,37 +1,141 @@
+use std::{sync::Arc, time::Duration};
 use anyhow::Context;
 use futures::FutureExt;
-use tracing::{info, level_filters::LevelFilter};
-use tracing_subscriber::FmtSubscriber;
+use once_cell::sync::Lazy;
+use tracing::{error, info, level_filters::LevelFilter, warn};
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::{EnvFilter, Layer, fmt::{self, format::FmtSpan, writer::BoxMakeWriter}, layer::SubscriberExt};
 
-use crate::{global::CONFIG, ui::UiContext};
+use lfs_stt::{audio::{self, audio_pipeline::InputDeviceEvent}, config::{self, LogRotation}, models};
 
+use crate::{global::{self, ARGS, CONFIG}, ui::UiContext};
+
+mod cli;
+#[cfg(feature = "clipboard")]
+mod clipboard;
+mod global;
 mod insim_io;
 mod ui;
-mod audio;
-mod config;
-mod global;
+mod file_input;
+mod standalone;
+mod state;
+mod stats;
+
+/// How long to wait for InSim cleanup on Ctrl-C before exiting anyway.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(2);
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let subscriber = FmtSubscriber::builder()
-        .with_max_level(LevelFilter::from(CONFIG.debug_log_level))
-        .finish();
+    Lazy::force(&ARGS);
+    if ARGS.list_devices {
+        return print_input_devices();
+    }
+
+    if let Err(errors) = CONFIG.validate(&global::model_path()) {
+        eprintln!("Invalid {}:", config::CONFIG_PATH);
+        for error in &errors {
+            eprintln!("  - {}", error);
+        }
+        anyhow::bail!("{} problem(s) found in {}", errors.len(), config::CONFIG_PATH);
+    }
+
+    // RUST_LOG takes precedence over --log-level/-v, which take precedence over debug_log_level,
+    // e.g. RUST_LOG=lfs_stt=debug
+    let log_level = ARGS.log_level.unwrap_or(CONFIG.debug_log_level);
+    let filter = EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| EnvFilter::default().add_directive(LevelFilter::from(log_level).into()));
+    // Standalone and file input modes print transcriptions to stdout, keep the logs out of the way.
+    let writer = if let Some(path) = &ARGS.log_file {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open log file {}", path))?;
+        BoxMakeWriter::new(Arc::new(file))
+    } else if ARGS.standalone || ARGS.input_file.is_some() {
+        BoxMakeWriter::new(std::io::stderr)
+    } else {
+        BoxMakeWriter::new(std::io::stdout)
+    };
+    let console_layer = fmt::layer()
+        .with_span_events(FmtSpan::CLOSE)
+        .with_ansi(ARGS.log_file.is_none())
+        .with_writer(writer);
+    // Rotating log files in log_dir are written in addition to the console output.
+    let file_layer = match &CONFIG.log_dir {
+        Some(dir) => {
+            let rotation = match CONFIG.log_rotation {
+                LogRotation::Hourly => Rotation::HOURLY,
+                LogRotation::Daily => Rotation::DAILY,
+            };
+            let appender = RollingFileAppender::builder()
+                .rotation(rotation)
+                .filename_prefix("lfs-stt")
+                .filename_suffix("log")
+                .max_log_files(CONFIG.max_log_files)
+                .build(dir)
+                .with_context(|| format!("Failed to create log directory {}", dir))?;
+            Some(fmt::layer()
+                .with_span_events(FmtSpan::CLOSE)
+                .with_ansi(false)
+                .with_writer(appender)
+                .boxed())
+        },
+        None => None,
+    };
+    let subscriber = tracing_subscriber::registry()
+        .with(filter)
+        .with(console_layer)
+        .with(file_layer);
     tracing::subscriber::set_global_default(subscriber)
         .expect("setting default subscriber failed");
+    log_startup_info();
 
-    let (insim, mut insim_rx, insim_handle) =
-        insim_io::init_insim()
+    let model_path = global::model_path();
+    match &ARGS.model_path {
+        Some(path) => info!("Using model {} from command line (config model_path = {})", path, CONFIG.model_path),
+        None => info!("Using model {} from config", CONFIG.model_path),
+    }
+    for warning in CONFIG.model_warnings(&model_path) {
+        warn!("{}", warning);
+    }
+    if CONFIG.auto_download {
+        let name = models::model_name(&model_path);
+        models::ensure_model(&name, std::path::Path::new(&model_path))
             .await
-            .context("Failed to initialize insim io")?;
+            .context("Failed to download model")?;
+    }
+
+    if let Some(path) = &ARGS.input_file {
+        return file_input::run(path, model_path).await;
+    }
+
+    // Audio pipeline first, so model and device problems are reported before connecting to LFS.
     let (mut audio_pipeline, mut stt_rx, audio_pipeline_handle) =
-        audio::audio_pipeline::AudioPipeline::new()
+        audio::audio_pipeline::AudioPipeline::new(model_path, Arc::clone(&CONFIG))
             .await
             .context("Failed to initialize audio pipeline")?;
+    if ARGS.standalone {
+        return standalone::run(audio_pipeline, stt_rx, audio_pipeline_handle).await;
+    }
+    let (mut insim, mut insim_rx, insim_handle) =
+        insim_io::init_insim()
+            .await
+            .context("Failed to initialize insim io")?;
 
     let mut ui_context = UiContext::default();
 
+    let mut input_level_rx = audio_pipeline.input_level();
+    let mut ui_tick = tokio::time::interval(ui::TICK_INTERVAL);
+    ui_tick.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+
     let mut audio_pipeline_handle = audio_pipeline_handle.fuse();
     let mut insim_handle = insim_handle.fuse();
+    // Connecting again after the connection was lost, runs until LFS is back.
+    let mut reconnect = None;
+
+    let ctrl_c = tokio::signal::ctrl_c();
+    tokio::pin!(ctrl_c);
 
     loop {
         // Always dispatch UI events first
@@ -41,9 +145,34 @@ async fn main() -> anyhow::Result<()> {
             // Clear any UI message timeout
             _ = ui_context.clear_message_timeout() => {},
 
+            // Put the preview back after the no-speech hint
+            _ = ui_context.clear_hint_timeout() => {},
+
+            // Apply in-game changes once they are stable
+            _ = ui_context.apply_in_game_change() => {},
+
+            // Refresh live UI elements
+            _ = ui_tick.tick() => {
+                ui_context.tick();
+            },
+
+            // Update input level meter
+            Ok(()) = input_level_rx.changed() => {
+                let level = *input_level_rx.borrow_and_update();
+                ui_context.handle_input_level(level);
+            },
+
+            event = audio_pipeline.input_device_event() => {
+                match event {
+                    InputDeviceEvent::Lost => warn!("Audio input device lost, waiting for it to come back..."),
+                    InputDeviceEvent::Restored => info!("Audio input device is back"),
+                }
+                ui_context.handle_input_device_event(event);
+            },
+
             // Process STT messages
             Some(msg) = stt_rx.recv() => {
-                ui_context.handle_stt_message(msg);
+                ui_context.handle_stt_message(msg, insim.clone()).await;
             },
 
             // Process Insim events
@@ -53,19 +182,53 @@ async fn main() -> anyhow::Result<()> {
 
             res = &mut insim_handle => {
                 match res {
-                    Ok(Ok(())) => info!("Insim task ended successfully."),
-                    Ok(Err(e)) => { return Err(e).context("Insim task ended with an error") },
+                    Ok(Ok(())) => info!("Insim connection closed, reconnecting..."),
+                    Ok(Err(e)) => warn!("Insim connection lost ({}), reconnecting...", e),
+                    // Aborted by the keepalive watchdog.
+                    Err(e) if e.is_cancelled() => warn!("Insim connection stopped responding, reconnecting..."),
                     Err(e) => { return Err(e).context("Insim task panicked")},
                 }
+                ui_context.set_in_game(false);
+                // Retries until LFS is back, the loop keeps serving everything else meanwhile.
+                reconnect = Some(tokio::spawn(insim_io::init_insim()));
+            },
+            res = async { reconnect.as_mut().expect("checked by the precondition").await }, if reconnect.is_some() => {
+                reconnect = None;
+                let (new_insim, new_insim_rx, new_insim_handle) = match res {
+                    Ok(connection) => connection.context("Failed to reconnect insim io")?,
+                    Err(e) => { return Err(e).context("Insim reconnect task panicked")},
+                };
+                insim = new_insim;
+                insim_rx = new_insim_rx;
+                insim_handle = new_insim_handle.fuse();
+            },
+            _ = &mut ctrl_c => {
+                info!("Shutting down...");
+                if let Some(reconnect) = reconnect.take() {
+                    reconnect.abort();
+                }
+                ui_context.remove_all_buttons();
+                let cleanup = async {
+                    ui_context.dispatch_ui_events(insim.clone()).await;
+                    // Give the insim task a moment to write the packets out.
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                };
+                if tokio::time::timeout(SHUTDOWN_TIMEOUT, cleanup).await.is_err() {
+                    warn!("Timed out removing InSim buttons");
+                }
+                audio_pipeline.shutdown();
+                ui_context.stats().log_summary();
                 break;
             },
+            // Keep running without the pipeline so the failure is visible in game.
             res = &mut audio_pipeline_handle => {
                 match res {
-                    Ok(Ok(())) => info!("Audio pipeline task ended successfully."),
-                    Ok(Err(e)) => { return Err(e).context("Audio pipeline task ended with an error") },
-                    Err(e) => { return Err(e).context("Audio pipeline task panicked")},
+                    // The pipeline runs until shutdown, ending on its own is a failure too.
+                    Ok(Ok(())) => error!("Audio pipeline task ended unexpectedly"),
+                    Ok(Err(e)) => error!("Audio pipeline task ended with an error: {:#}", anyhow::Error::from(e)),
+                    Err(e) => error!("Audio pipeline task panicked: {}", e),
                 }
-                break;
+                ui_context.handle_pipeline_failure();
             },
         }
     }
@@ -73,3 +236,48 @@ async fn main() -> anyhow::Result<()> {
     Ok(())
 }
 
+
+/// Version, environment and resolved config, so a log file is enough to reproduce a bug report.
+fn log_startup_info() {
+    let features: Vec<&str> = [("gpu", cfg!(feature = "gpu")), ("cpu-only", cfg!(feature = "cpu-only")), ("clipboard", cfg!(feature = "clipboard"))]
+        .into_iter()
+        .filter_map(|(name, enabled)| enabled.then_some(name))
+        .collect();
+    info!(
+        "lfs-stt {} on {} {}, features: [{}]",
+        env!("CARGO_PKG_VERSION"),
+        std::env::consts::OS,
+        std::env::consts::ARCH,
+        features.join(", "),
+    );
+    info!("Arguments: {:?}", *ARGS);
+    if let Ok(dir) = std::env::current_dir() {
+        info!("Working directory: {}", dir.display());
+    }
+    info!("Config: {:?}", *CONFIG);
+}
+
+fn print_input_devices() -> anyhow::Result<()> {
+    let devices = audio::recorder::list_input_devices()
+        .context("Failed to list audio input devices")?;
+    if devices.is_empty() {
+        println!("No audio input devices found.");
+        return Ok(());
+    }
+
+    println!("Available audio input devices:");
+    for (index, device) in devices.iter().enumerate() {
+        let channels = device.input_channels.map_or("?".to_string(), |c| c.to_string());
+        let sample_rate = device.sample_rate.map_or("?".to_string(), |r| r.to_string());
+        println!(
+            "  [{}] {} ({} ch, {} Hz){}",
+            index,
+            device.name,
+            channels,
+            sample_rate,
+            if device.is_default { " (default)" } else { "" },
+        );
+    }
+
+    Ok(())
+}
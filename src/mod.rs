@@ -1,8 +0,0 @@
-pub mod audio_input;
-pub mod insim_io;
-pub mod resampler;
-pub mod stt;
-pub mod ui;
-pub mod audio;
-pub mod config;
-pub mod global;
@@ -0,0 +1,124 @@
+use std::path::{Path, PathBuf};
+use sha1::{Digest, Sha1};
+use tokio::io::AsyncWriteExt;
+use tracing::{info, warn};
+
+const MODEL_BASE_URL: &str = "https://huggingface.co/ggerganov/whisper.cpp/resolve/main";
+
+/// Official ggml models and their SHA-1 checksums, as listed in the whisper.cpp repository.
+const KNOWN_MODELS: &[(&str, &str)] = &[
+    ("tiny", "bd577a113a864445d4c299885e0cb97d4ba92b5f"),
+    ("tiny.en", "c78c86eb1a8faa21b369bcd33207cc90d64ae9df"),
+    ("base", "465707469ff3a37a2b9b8d8f89f2f99de7299dac"),
+    ("base.en", "137c40403d78fd54d454da0f9bd998f78703390c"),
+    ("small", "55356645c2b361a969dfd0ef2c5a50d530afd8d5"),
+    ("small.en", "db8a495a91d927739e50b3fc1cc4c6b8f6c2d022"),
+    ("medium", "fd9727b6e1217c2f614f9b698455c4ffd82463b4"),
+    ("medium.en", "8c30f0e44ce9560643ebd10bbe50cd20eafd3723"),
+    ("large-v3", "ad82bf6a9043ceed055076d0fd39f5f186ff8062"),
+    ("large-v3-turbo", "4af2b29d7ec73d781377bfd1758ca957a807e941"),
+];
+
+#[derive(Debug, thiserror::Error)]
+pub enum ModelError {
+    #[error("unknown model \"{0}\", automatic download supports: {1}")]
+    UnknownModel(String, String),
+
+    #[error("model file IO error")]
+    Io(#[from] std::io::Error),
+
+    #[error("failed to download model")]
+    Download(#[from] reqwest::Error),
+
+    #[error("checksum mismatch for downloaded model, expected {expected} but got {actual}")]
+    ChecksumMismatch { expected: String, actual: String },
+}
+
+/// Model name from a model file path, e.g. `models/ggml-small.en.bin` -> `small.en`.
+pub fn model_name(model_path: &str) -> String {
+    let stem = Path::new(model_path)
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or(model_path);
+    stem.strip_prefix("ggml-").unwrap_or(stem).to_string()
+}
+
+/// English-only whisper models are distributed with an `.en` suffix, e.g. `small.en.bin`.
+pub fn is_english_only_model(model_path: &str) -> bool {
+    model_name(model_path).ends_with(".en")
+}
+
+/// Make sure the named model exists at `dest`, downloading it if missing.
+/// An existing file is always kept, a checksum mismatch is only reported since it may be
+/// a custom or fine-tuned model saved under an official name.
+pub async fn ensure_model(name: &str, dest: &Path) -> Result<PathBuf, ModelError> {
+    let expected = KNOWN_MODELS.iter().find(|(model, _)| *model == name).map(|(_, sha1)| *sha1);
+
+    if dest.is_file() {
+        if let Some(expected) = expected {
+            let actual = file_sha1(dest.to_path_buf()).await?;
+            if actual != expected {
+                warn!("Model at {} does not match the checksum of the official {} model, using it anyway. Delete it to download the official model again.", dest.display(), name);
+            }
+        }
+        return Ok(dest.to_path_buf());
+    }
+
+    let Some(expected) = expected else {
+        let known = KNOWN_MODELS.iter().map(|(model, _)| *model).collect::<Vec<_>>().join(", ");
+        return Err(ModelError::UnknownModel(name.to_string(), known));
+    };
+
+    if let Some(dir) = dest.parent() {
+        tokio::fs::create_dir_all(dir).await?;
+    }
+
+    let url = format!("{}/ggml-{}.bin", MODEL_BASE_URL, name);
+    info!("Downloading model {} from {}", name, url);
+
+    let part_path = dest.with_extension("part");
+    let mut response = reqwest::get(&url).await?.error_for_status()?;
+    let total = response.content_length();
+    let mut file = tokio::fs::File::create(&part_path).await?;
+    let mut hasher = Sha1::new();
+    let mut downloaded: u64 = 0;
+    let mut logged_percent: u64 = 0;
+
+    while let Some(chunk) = response.chunk().await? {
+        hasher.update(&chunk);
+        file.write_all(&chunk).await?;
+        downloaded += chunk.len() as u64;
+
+        if let Some(total) = total.filter(|total| *total > 0) {
+            let percent = downloaded * 100 / total;
+            if percent >= logged_percent + 10 {
+                logged_percent = percent - percent % 10;
+                info!("Downloading model {}: {}% ({} / {} MB)", name, logged_percent, downloaded / 1_000_000, total / 1_000_000);
+            }
+        }
+    }
+    file.flush().await?;
+    drop(file);
+
+    let actual = format!("{:x}", hasher.finalize());
+    if actual != expected {
+        let _ = tokio::fs::remove_file(&part_path).await;
+        return Err(ModelError::ChecksumMismatch { expected: expected.to_string(), actual });
+    }
+
+    tokio::fs::rename(&part_path, dest).await?;
+    info!("Model {} saved to {}", name, dest.display());
+
+    Ok(dest.to_path_buf())
+}
+
+async fn file_sha1(path: PathBuf) -> Result<String, std::io::Error> {
+    tokio::task::spawn_blocking(move || {
+        let mut file = std::fs::File::open(path)?;
+        let mut hasher = Sha1::new();
+        std::io::copy(&mut file, &mut hasher)?;
+        Ok::<_, std::io::Error>(format!("{:x}", hasher.finalize()))
+    })
+    .await
+    .map_err(std::io::Error::other)?
+}
@@ -0,0 +1,91 @@
+use anyhow::Context;
+use tokio::{io::{AsyncBufReadExt, BufReader}, sync::mpsc::Receiver, task::JoinHandle};
+use tracing::info;
+
+use lfs_stt::audio::{AudioPipelineError, audio_pipeline::{AudioPipeline, InputDeviceEvent}, speech_to_text::{SttMessage, SttMessageType}};
+
+#[cfg(feature = "clipboard")]
+use crate::{clipboard::ClipboardSink, global::CONFIG};
+use crate::stats::SessionStats;
+
+/// Dictation without LFS: Enter toggles recording, transcriptions are printed to stdout.
+pub async fn run(
+    mut audio_pipeline: AudioPipeline,
+    mut stt_rx: Receiver<SttMessage>,
+    mut audio_pipeline_handle: JoinHandle<Result<(), AudioPipelineError>>,
+) -> anyhow::Result<()> {
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    let mut recording = false;
+    let mut stats = SessionStats::default();
+    #[cfg(feature = "clipboard")]
+    let mut clipboard = CONFIG.copy_to_clipboard.then(ClipboardSink::new);
+    eprintln!("Press Enter to start recording, Ctrl-C to exit.");
+
+    let ctrl_c = tokio::signal::ctrl_c();
+    tokio::pin!(ctrl_c);
+
+    loop {
+        tokio::select! {
+            line = lines.next_line() => {
+                if line.context("Failed to read from stdin")?.is_none() {
+                    info!("Stdin closed, shutting down...");
+                    break;
+                }
+
+                if recording {
+                    eprintln!("Transcribing...");
+                    audio_pipeline.stop_recording_and_transcribe().await;
+                } else {
+                    eprintln!("Recording, press Enter to stop.");
+                    audio_pipeline.start_recording().await;
+                }
+                recording = !recording;
+            },
+            event = audio_pipeline.input_device_event() => {
+                match event {
+                    InputDeviceEvent::Lost => {
+                        recording = false;
+                        eprintln!("Audio input device lost, waiting for it to come back...");
+                    },
+                    InputDeviceEvent::Restored => eprintln!("Audio input device is back."),
+                }
+            },
+            Some(msg) = stt_rx.recv() => {
+                match msg.msg_type {
+                    SttMessageType::TranscriptionResult => {
+                        stats.record(&msg.content, msg.audio_ms, msg.processing_ms);
+                        #[cfg(feature = "clipboard")]
+                        if let Some(clipboard) = clipboard.as_mut() {
+                            clipboard.copy(&msg.content);
+                        }
+                        println!("{}", msg.content);
+                    },
+                    SttMessageType::RecordingTimeoutReached => {
+                        recording = false;
+                        eprintln!("Recording timeout reached, transcribing...");
+                    },
+                    SttMessageType::NoSpeech => eprintln!("No speech detected."),
+                    SttMessageType::RecordingTooShort => eprintln!("Recording too short, discarded."),
+                    SttMessageType::ModelReloaded => eprintln!("Model reloaded from {}.", msg.content),
+                    SttMessageType::TranscriptionError => eprintln!("{}", msg),
+                }
+            },
+            _ = &mut ctrl_c => {
+                info!("Shutting down...");
+                break;
+            },
+            res = &mut audio_pipeline_handle => {
+                match res {
+                    Ok(Ok(())) => info!("Audio pipeline task ended successfully."),
+                    Ok(Err(e)) => { return Err(e).context("Audio pipeline task ended with an error") },
+                    Err(e) => { return Err(e).context("Audio pipeline task panicked")},
+                }
+                return Ok(());
+            },
+        }
+    }
+
+    audio_pipeline.shutdown();
+    stats.log_summary();
+    Ok(())
+}
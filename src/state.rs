@@ -0,0 +1,42 @@
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+pub const STATE_PATH: &str = "state.toml";
+
+/// Small bits of UI state persisted across restarts.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct AppState {
+    pub active_channel_prefix: Option<String>,
+}
+
+impl AppState {
+    pub fn load() -> Self {
+        let contents = match std::fs::read_to_string(STATE_PATH) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Self::default(),
+            Err(e) => {
+                warn!("Failed to read {}: {}", STATE_PATH, e);
+                return Self::default();
+            }
+        };
+
+        toml::from_str(&contents).unwrap_or_else(|e| {
+            warn!("Failed to parse {}: {}", STATE_PATH, e);
+            Self::default()
+        })
+    }
+
+    pub fn save(&self) {
+        let contents = match toml::to_string(self) {
+            Ok(contents) => contents,
+            Err(e) => {
+                warn!("Failed to serialize state: {}", e);
+                return;
+            }
+        };
+
+        if let Err(e) = std::fs::write(STATE_PATH, contents) {
+            warn!("Failed to write {}: {}", STATE_PATH, e);
+        }
+    }
+}
@@ -0,0 +1,43 @@
+use tracing::info;
+
+use crate::global::CONFIG;
+
+/// Usage numbers for the current session, logged on shutdown.
+#[derive(Debug, Default)]
+pub struct SessionStats {
+    recordings: u64,
+    words: u64,
+    audio_ms: u64,
+    processing_ms: u64,
+}
+
+impl SessionStats {
+    /// Count a finished transcription, logging a summary every `stats_log_interval` recordings.
+    pub fn record(&mut self, text: &str, audio_ms: u64, processing_ms: u64) {
+        self.recordings += 1;
+        self.words += text.split_whitespace().count() as u64;
+        self.audio_ms += audio_ms;
+        self.processing_ms += processing_ms;
+
+        let interval = CONFIG.stats_log_interval;
+        if interval > 0 && self.recordings.is_multiple_of(interval) {
+            self.log_summary();
+        }
+    }
+
+    pub fn log_summary(&self) {
+        if self.recordings == 0 {
+            info!("Session stats: nothing was transcribed");
+            return;
+        }
+
+        let avg_audio_secs = self.audio_ms as f64 / self.recordings as f64 / 1000.0;
+        let avg_latency_secs = self.processing_ms as f64 / self.recordings as f64 / 1000.0;
+        // Rough, based on the recorded audio including pauses.
+        let wpm = if self.audio_ms > 0 { self.words as f64 * 60_000.0 / self.audio_ms as f64 } else { 0.0 };
+        info!(
+            "Session stats: {} recordings, {} words, avg recording {:.1}s, avg transcription {:.1}s, ~{:.0} WPM",
+            self.recordings, self.words, avg_audio_secs, avg_latency_secs, wpm
+        );
+    }
+}
@@ -0,0 +1,561 @@
+use std::collections::HashMap;
+
+use crate::config::TextCase;
+
+/// Remove whisper's non-speech annotations like `[BLANK_AUDIO]` or `(engine revving)`
+/// and collapse the leftover whitespace.
+pub fn strip_non_speech(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find(['[', '(']) {
+        let close = if rest[start..].starts_with('[') { ']' } else { ')' };
+        match rest[start + 1..].find(close) {
+            Some(end) => {
+                result.push_str(&rest[..start]);
+                rest = &rest[start + 1 + end + 1..];
+            },
+            None => {
+                // No closing bracket, keep the opening one as regular text.
+                result.push_str(&rest[..=start]);
+                rest = &rest[start + 1..];
+            },
+        }
+    }
+    result.push_str(rest);
+
+    result.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Drop filler words like "um" or "uh" where the whole word matches, ignoring case.
+/// Commas and the like go with the filler, sentence-ending punctuation moves to the word before it.
+pub fn remove_fillers(text: &str, fillers: &[String]) -> String {
+    let fillers: Vec<String> = fillers.iter().map(|filler| filler.trim().to_lowercase()).collect();
+    let mut words: Vec<String> = Vec::new();
+    for word in text.split_whitespace() {
+        let (core, punctuation) = split_punctuation(word);
+        if !fillers.contains(&core) {
+            words.push(word.to_string());
+            continue;
+        }
+
+        if punctuation.contains(['.', '!', '?'])
+            && let Some(last) = words.last_mut()
+        {
+            let core_len = last.trim_end_matches(['.', ',', '!', '?', ';', ':']).len();
+            last.truncate(core_len);
+            last.push_str(punctuation);
+        }
+    }
+    words.join(" ")
+}
+
+/// Capitalize the first letter and end the message with a period unless it already ends
+/// with punctuation.
+pub fn tidy(message: &str) -> String {
+    let mut result = capitalize_first(message.trim());
+    if !result.is_empty() && !result.ends_with(|c: char| c.is_ascii_punctuation()) {
+        result.push('.');
+    }
+    result
+}
+
+fn capitalize_first(text: &str) -> String {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+/// Change the letter case of a message, see `TextCase`.
+pub fn apply_case(text: &str, case: TextCase) -> String {
+    match case {
+        TextCase::Preserve => text.to_string(),
+        TextCase::Lower => text.to_lowercase(),
+        TextCase::Upper => text.to_uppercase(),
+        TextCase::Sentence => text
+            .split_inclusive(['.', '!', '?'])
+            .map(|sentence| {
+                // Keep the whitespace between sentences where it was.
+                let start = sentence.len() - sentence.trim_start().len();
+                format!("{}{}", &sentence[..start], capitalize_first(&sentence[start..]))
+            })
+            .collect(),
+    }
+}
+
+/// Replace whole words or phrases, matched case-insensitively.
+/// Longer phrases are tried first, so "pit in" wins over "pit". Replaced text is not matched again.
+pub fn apply_replacements(text: &str, replacements: &HashMap<String, String>) -> String {
+    let mut keys: Vec<(Vec<char>, &str)> = replacements.iter()
+        .filter(|(key, _)| !key.trim().is_empty())
+        .map(|(key, value)| (key.trim().chars().collect(), value.as_str()))
+        .collect();
+    keys.sort_by(|a, b| b.0.len().cmp(&a.0.len()).then_with(|| a.0.cmp(&b.0)));
+
+    let chars: Vec<char> = text.chars().collect();
+    let mut result = String::with_capacity(text.len());
+    let mut i = 0;
+    while i < chars.len() {
+        if i == 0 || !is_word_char(chars[i - 1]) {
+            let replacement = keys.iter()
+                .find(|(key, _)| matches_word_at(&chars, i, key))
+                .map(|(key, value)| (key.len(), *value));
+            if let Some((len, value)) = replacement {
+                result.push_str(value);
+                i += len;
+                continue;
+            }
+        }
+        result.push(chars[i]);
+        i += 1;
+    }
+
+    result
+}
+
+/// Replace whole words from `words`, matched case-insensitively, with asterisks of the same length.
+pub fn censor(message: &str, words: &[String]) -> String {
+    let words: Vec<Vec<char>> = words.iter()
+        .filter(|word| !word.trim().is_empty())
+        .map(|word| word.trim().chars().collect())
+        .collect();
+
+    let chars: Vec<char> = message.chars().collect();
+    let mut result = String::with_capacity(message.len());
+    let mut i = 0;
+    while i < chars.len() {
+        if i == 0 || !is_word_char(chars[i - 1]) {
+            let longest = words.iter()
+                .filter(|word| matches_word_at(&chars, i, word))
+                .map(|word| word.len())
+                .max();
+            if let Some(len) = longest {
+                result.extend(std::iter::repeat_n('*', len));
+                i += len;
+                continue;
+            }
+        }
+        result.push(chars[i]);
+        i += 1;
+    }
+
+    result
+}
+
+/// Convert spelled out numbers below 1000 to digits, e.g. "turn twenty one" becomes "turn 21".
+/// A lone "one" is left alone, it is more often a pronoun than a number, and so is "oh".
+pub fn words_to_digits(text: &str) -> String {
+    let words: Vec<&str> = text.split_whitespace().collect();
+    let mut result: Vec<String> = Vec::with_capacity(words.len());
+    let mut i = 0;
+    while i < words.len() {
+        match parse_number(&words[i..]) {
+            Some((value, used, trailing)) if !(value == 1 && used == 1) => {
+                result.push(format!("{}{}", value, trailing));
+                i += used;
+            },
+            _ => {
+                result.push(words[i].to_string());
+                i += 1;
+            },
+        }
+    }
+    result.join(" ")
+}
+
+/// Number at the start of `words` as (value, words used, punctuation after the last word).
+fn parse_number<'a>(words: &[&'a str]) -> Option<(u32, usize, &'a str)> {
+    let (mut value, mut used, mut trailing) = parse_below_hundred(words)?;
+    if trailing.is_empty()
+        && (1..=9).contains(&value)
+        && let Some(next) = words.get(used)
+    {
+        let (core, punctuation) = split_punctuation(next);
+        if core == "hundred" {
+            value *= 100;
+            used += 1;
+            trailing = punctuation;
+            // "one hundred and five"
+            let skip_and = usize::from(words.get(used).is_some_and(|w| w.eq_ignore_ascii_case("and")));
+            if trailing.is_empty()
+                && let Some((rest, rest_used, rest_trailing)) = parse_below_hundred(&words[used + skip_and..])
+            {
+                value += rest;
+                used += skip_and + rest_used;
+                trailing = rest_trailing;
+            }
+        }
+    }
+    Some((value, used, trailing))
+}
+
+fn parse_below_hundred<'a>(words: &[&'a str]) -> Option<(u32, usize, &'a str)> {
+    let (core, trailing) = split_punctuation(words.first()?);
+    if let Some((tens, unit)) = core.split_once('-') {
+        let value = tens_value(tens)? + unit_value(unit).filter(|u| (1..=9).contains(u))?;
+        return Some((value, 1, trailing));
+    }
+
+    if let Some(tens) = tens_value(&core) {
+        if trailing.is_empty()
+            && let Some(next) = words.get(1)
+        {
+            let (next_core, next_trailing) = split_punctuation(next);
+            if let Some(unit) = unit_value(&next_core).filter(|u| (1..=9).contains(u)) {
+                return Some((tens + unit, 2, next_trailing));
+            }
+        }
+        return Some((tens, 1, trailing));
+    }
+
+    unit_value(&core).map(|value| (value, 1, trailing))
+}
+
+/// Lowercased word and the punctuation it ends with.
+fn split_punctuation(word: &str) -> (String, &str) {
+    let core = word.trim_end_matches(['.', ',', '!', '?', ';', ':']);
+    (core.to_lowercase(), &word[core.len()..])
+}
+
+fn unit_value(word: &str) -> Option<u32> {
+    let value = match word {
+        "zero" => 0,
+        "one" => 1,
+        "two" => 2,
+        "three" => 3,
+        "four" => 4,
+        "five" => 5,
+        "six" => 6,
+        "seven" => 7,
+        "eight" => 8,
+        "nine" => 9,
+        "ten" => 10,
+        "eleven" => 11,
+        "twelve" => 12,
+        "thirteen" => 13,
+        "fourteen" => 14,
+        "fifteen" => 15,
+        "sixteen" => 16,
+        "seventeen" => 17,
+        "eighteen" => 18,
+        "nineteen" => 19,
+        _ => return None,
+    };
+    Some(value)
+}
+
+fn tens_value(word: &str) -> Option<u32> {
+    let value = match word {
+        "twenty" => 20,
+        "thirty" => 30,
+        "forty" => 40,
+        "fifty" => 50,
+        "sixty" => 60,
+        "seventy" => 70,
+        "eighty" => 80,
+        "ninety" => 90,
+        _ => return None,
+    };
+    Some(value)
+}
+
+/// Lowercase, drop punctuation and collapse whitespace, used to match spoken phrases loosely.
+pub fn normalize_phrase(text: &str) -> String {
+    text.chars()
+        .filter(|c| is_word_char(*c) || c.is_whitespace())
+        .flat_map(char::to_lowercase)
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Split a message into chunks of at most `max_len` visible characters, breaking at whitespace.
+/// LFS colour codes like `^3` are not counted against the limit but are kept in the chunks,
+/// and escape sequences are never split across two chunks.
+/// Each chunk is also kept within `max_bytes` once escaped for LFS, which matters for text
+/// with multibyte characters or characters LFS needs escaped.
+/// Only a single word over either limit is split mid-word. Whitespace between words is
+/// collapsed to a single space.
+pub fn split_message(message: &str, max_len: usize, max_bytes: usize) -> Vec<String> {
+    let max_len = max_len.max(1);
+    let max_bytes = max_bytes.max(1);
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+    let mut current_len = 0;
+    let mut current_bytes = 0;
+
+    for word in message.split_whitespace() {
+        let word_len = visible_len(word);
+        let word_bytes = escaped_len(word);
+        let separator = usize::from(!current.is_empty());
+        if current_len + separator + word_len <= max_len && current_bytes + separator + word_bytes <= max_bytes {
+            if separator > 0 {
+                current.push(' ');
+            }
+            current.push_str(word);
+            current_len += separator + word_len;
+            current_bytes += separator + word_bytes;
+            continue;
+        }
+
+        if !current.is_empty() {
+            chunks.push(std::mem::take(&mut current));
+        }
+
+        let mut piece = String::new();
+        let mut piece_len = 0;
+        let mut piece_bytes = 0;
+        for unit in escape_units(word) {
+            // Colour codes count as one character here and need room for one more byte,
+            // so a code never ends a full chunk and the colour carries over to the text
+            // that follows it.
+            let unit_len = visible_len(unit);
+            let unit_bytes = escaped_len(unit);
+            let is_code = unit_len == 0;
+            if !piece.is_empty()
+                && (piece_len + unit_len.max(1) > max_len || piece_bytes + unit_bytes + usize::from(is_code) > max_bytes)
+            {
+                chunks.push(std::mem::take(&mut piece));
+                piece_len = 0;
+                piece_bytes = 0;
+            }
+            piece.push_str(unit);
+            piece_len += unit_len;
+            piece_bytes += unit_bytes;
+        }
+        current = piece;
+        current_len = piece_len;
+        current_bytes = piece_bytes;
+    }
+
+    if !current.is_empty() {
+        chunks.push(current);
+    }
+    chunks
+}
+
+/// `split_message` limits for the text after `prefix` and a space on a chat line of `line_len`.
+/// Both are at least 1, even when the prefix alone fills the line.
+pub fn chunk_limits(prefix: &str, line_len: usize) -> (usize, usize) {
+    let max_len = line_len.saturating_sub(prefix.len()).max(1);
+    // The byte limit also covers the space after the prefix.
+    let max_bytes = line_len.saturating_sub(prefix.len() + 1).max(1);
+    (max_len, max_bytes)
+}
+
+/// Split text into characters, keeping `^` escape sequences like `^3` together as one unit.
+fn escape_units(text: &str) -> Vec<&str> {
+    let mut units = Vec::new();
+    let mut indices = text.char_indices().peekable();
+    while let Some((start, c)) = indices.next() {
+        let mut end = start + c.len_utf8();
+        if c == '^'
+            && let Some((next, next_c)) = indices.next()
+        {
+            end = next + next_c.len_utf8();
+        }
+        units.push(&text[start..end]);
+    }
+    units
+}
+
+/// Number of characters shown in LFS, colour codes excluded.
+fn visible_len(text: &str) -> usize {
+    insim::core::string::colours::strip(text).chars().count()
+}
+
+/// Length in bytes once escaped for LFS.
+fn escaped_len(text: &str) -> usize {
+    insim::core::string::escaping::escape(text).len()
+}
+
+/// Whether `word` is found at `start`, ending on a word boundary.
+fn matches_word_at(chars: &[char], start: usize, word: &[char]) -> bool {
+    let end = start + word.len();
+    if end > chars.len() {
+        return false;
+    }
+
+    let matches = chars[start..end].iter()
+        .zip(word)
+        .all(|(a, b)| a.to_lowercase().eq(b.to_lowercase()));
+    matches && (end == chars.len() || !is_word_char(chars[end]))
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '\''
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(words: &[&str]) -> Vec<String> {
+        words.iter().map(|word| word.to_string()).collect()
+    }
+
+    #[test]
+    fn strip_non_speech_removes_tags() {
+        assert_eq!(strip_non_speech("[BLANK_AUDIO]"), "");
+        assert_eq!(strip_non_speech("box box [MUSIC] this lap"), "box box this lap");
+        assert_eq!(strip_non_speech("pit (engine revving) now"), "pit now");
+        assert_eq!(strip_non_speech("(laughs) [Music] yes"), "yes");
+    }
+
+    #[test]
+    fn strip_non_speech_keeps_unclosed_brackets() {
+        assert_eq!(strip_non_speech("turn (left"), "turn (left");
+        assert_eq!(strip_non_speech("a [b) c"), "a [b) c");
+    }
+
+    #[test]
+    fn remove_fillers_matches_whole_words() {
+        let fillers = strings(&["um", "uh"]);
+        assert_eq!(remove_fillers("um I think uh we pit", &fillers), "I think we pit");
+        assert_eq!(remove_fillers("UM hello", &fillers), "hello");
+        assert_eq!(remove_fillers("umbrella uhh", &fillers), "umbrella uhh");
+    }
+
+    #[test]
+    fn remove_fillers_moves_sentence_punctuation() {
+        let fillers = strings(&["um", "uh"]);
+        assert_eq!(remove_fillers("so, um, we pit", &fillers), "so, we pit");
+        assert_eq!(remove_fillers("we pit now, uh.", &fillers), "we pit now.");
+        assert_eq!(remove_fillers("really um?", &fillers), "really?");
+    }
+
+    #[test]
+    fn tidy_trims_capitalizes_and_ends_with_period() {
+        assert_eq!(tidy("  good race  "), "Good race.");
+        assert_eq!(tidy("are you pitting?"), "Are you pitting?");
+        assert_eq!(tidy("gg!"), "Gg!");
+        assert_eq!(tidy("   "), "");
+    }
+
+    #[test]
+    fn split_message_breaks_at_whitespace() {
+        assert_eq!(split_message("hello world this is a test", 10, 95), ["hello", "world this", "is a test"]);
+        assert_eq!(split_message("  spaced   out  ", 95, 95), ["spaced out"]);
+    }
+
+    #[test]
+    fn split_message_ignores_colour_codes_in_length() {
+        assert_eq!(split_message("^3green ^7white", 11, 95), ["^3green ^7white"]);
+        assert_eq!(split_message("^3green ^7white", 10, 95), ["^3green", "^7white"]);
+    }
+
+    #[test]
+    fn split_message_keeps_codes_with_the_following_text() {
+        assert_eq!(split_message("^1aaaaaaaaaa^3bbbb", 10, 95), ["^1aaaaaaaaaa", "^3bbbb"]);
+        // The byte limit has room for the code but not for a letter after it.
+        assert_eq!(split_message("aaaa^3bbbb", 95, 6), ["aaaa", "^3bbbb"]);
+    }
+
+    #[test]
+    fn split_message_splits_long_words() {
+        assert_eq!(split_message(&"a".repeat(25), 10, 95), ["a".repeat(10), "a".repeat(10), "a".repeat(5)]);
+    }
+
+    #[test]
+    fn split_message_caps_bytes_of_multibyte_words() {
+        let word = "\u{e9}".repeat(30);
+        let chunks = split_message(&word, 95, 20);
+        assert_eq!(chunks.concat(), word);
+        assert_eq!(chunks.len(), 3);
+        assert!(chunks.iter().all(|chunk| escaped_len(chunk) <= 20), "{:?}", chunks);
+    }
+
+    #[test]
+    fn split_message_caps_bytes_of_escaped_characters() {
+        // Each `|` takes two bytes once escaped.
+        assert_eq!(split_message("a|b|c d|e", 95, 6), ["a|b|", "c d|e"]);
+    }
+
+    #[test]
+    fn split_message_leaves_room_for_the_prefix() {
+        let (max_len, max_bytes) = chunk_limits("/say", 20);
+        assert_eq!((max_len, max_bytes), (16, 15));
+
+        let chunks = split_message("box this lap for fresh tyres please", max_len, max_bytes);
+        assert_eq!(chunks, ["box this lap", "for fresh tyres", "please"]);
+        assert!(chunks.iter().all(|chunk| format!("/say {}", chunk).len() <= 20), "{:?}", chunks);
+    }
+
+    #[test]
+    fn split_message_survives_a_prefix_longer_than_the_line() {
+        let prefix = "/msg ".repeat(30);
+        assert_eq!(chunk_limits(&prefix, 95), (1, 1));
+        assert_eq!(split_message("ok go", 1, 1), ["o", "k", "g", "o"]);
+    }
+
+    #[test]
+    fn censor_matches_whole_words_ignoring_case() {
+        let words = strings(&["darn"]);
+        assert_eq!(censor("Darn it, DARN!", &words), "**** it, ****!");
+        assert_eq!(censor("darned darnit undarn", &words), "darned darnit undarn");
+        assert_eq!(censor("(darn)", &words), "(****)");
+    }
+
+    #[test]
+    fn censor_prefers_the_longest_phrase() {
+        let words = strings(&["heck", "what the heck"]);
+        assert_eq!(censor("what the heck", &words), "*************");
+    }
+
+    #[test]
+    fn apply_replacements_matches_whole_words_ignoring_case() {
+        let replacements = HashMap::from([("pit".to_string(), "PIT".to_string())]);
+        assert_eq!(apply_replacements("Pit now, pit!", &replacements), "PIT now, PIT!");
+        assert_eq!(apply_replacements("pitlane spit", &replacements), "pitlane spit");
+    }
+
+    #[test]
+    fn apply_replacements_prefers_longer_phrases_and_does_not_rematch() {
+        let replacements = HashMap::from([
+            ("pit".to_string(), "box".to_string()),
+            ("pit in".to_string(), "pitting in".to_string()),
+        ]);
+        assert_eq!(apply_replacements("pit in. pit", &replacements), "pitting in. box");
+    }
+
+    #[test]
+    fn words_to_digits_converts_compounds() {
+        assert_eq!(words_to_digits("turn twenty one please"), "turn 21 please");
+        assert_eq!(words_to_digits("ninety-nine, go"), "99, go");
+        assert_eq!(words_to_digits("one hundred and five laps"), "105 laps");
+        assert_eq!(words_to_digits("Two Hundred fifty-five!"), "255!");
+        assert_eq!(words_to_digits("twenty. one"), "20. one");
+    }
+
+    #[test]
+    fn words_to_digits_converts_zero_but_not_oh() {
+        assert_eq!(words_to_digits("zero damage"), "0 damage");
+        assert_eq!(words_to_digits("oh no"), "oh no");
+        assert_eq!(words_to_digits("Oh, sector two."), "Oh, sector 2.");
+    }
+
+    #[test]
+    fn words_to_digits_leaves_non_numbers_alone() {
+        assert_eq!(words_to_digits("one of them"), "one of them");
+        assert_eq!(words_to_digits("that one is fine"), "that one is fine");
+        assert_eq!(words_to_digits("someone tonight often"), "someone tonight often");
+    }
+
+    #[test]
+    fn normalize_phrase_ignores_case_and_punctuation() {
+        assert_eq!(normalize_phrase("Box, box!"), "box box");
+        assert_eq!(normalize_phrase("  Good  LUCK. "), "good luck");
+        assert_eq!(normalize_phrase("Don't pit?"), "don't pit");
+    }
+
+    #[test]
+    fn normalize_phrase_keeps_longer_sentences_distinct() {
+        let phrases: HashMap<String, usize> = HashMap::from([(normalize_phrase("box box"), 0)]);
+        assert_eq!(phrases.get(&normalize_phrase("Box box.")), Some(&0));
+        assert_eq!(phrases.get(&normalize_phrase("I said box box")), None);
+        assert_eq!(phrases.get(&normalize_phrase("box box now")), None);
+    }
+}
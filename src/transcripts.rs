@@ -0,0 +1,46 @@
+use std::io::Write;
+use serde::Serialize;
+use tracing::warn;
+
+use crate::config::{Config, TranscriptFormat};
+
+pub struct TranscriptMeta<'a> {
+    pub model: &'a str,
+}
+
+#[derive(Serialize)]
+struct TranscriptEntry<'a> {
+    timestamp: String,
+    model: &'a str,
+    text: &'a str,
+}
+
+/// Append a transcription to `transcript_path`, if configured.
+pub fn log_result(config: &Config, text: &str, meta: TranscriptMeta) {
+    let Some(path) = &config.transcript_path else { return; };
+
+    let entry = TranscriptEntry {
+        timestamp: chrono::Local::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, false),
+        model: meta.model,
+        text,
+    };
+    let line = match config.transcript_format {
+        TranscriptFormat::Text => format!("[{}] [{}] {}", entry.timestamp, entry.model, entry.text),
+        TranscriptFormat::Json => match serde_json::to_string(&entry) {
+            Ok(line) => line,
+            Err(e) => {
+                warn!("Failed to serialize transcript entry: {}", e);
+                return;
+            }
+        },
+    };
+
+    let result = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| writeln!(file, "{}", line));
+    if let Err(e) = result {
+        warn!("Failed to write transcript to {}: {}", path, e);
+    }
+}
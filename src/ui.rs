@@ -1,14 +1,46 @@
-use std::pin::Pin;
+use std::{collections::HashMap, pin::Pin, time::Duration};
 use insim::builder::InsimTask;
-use tokio::time::Sleep;
-use tracing::{debug, error, info};
+use tokio::time::{Instant, Sleep};
+use tracing::{debug, error, info, warn};
 
-use crate::{audio::{audio_pipeline::AudioPipeline, speech_to_text::{SttMessage, SttMessageType}}, config::ChatChannel, global::CONFIG, insim_io::InsimEvent};
+use lfs_stt::{audio::{audio_pipeline::{AudioPipeline, InputDeviceEvent}, feedback, speech_to_text::{SttMessage, SttMessageType}}, config::{AnnouncementTarget, ChatChannel, UiColors}, text};
+
+use crate::{global::{ARGS, CONFIG}, insim_io::InsimEvent, state::AppState, stats::SessionStats};
 
 const MAX_MESSAGE_LEN: usize = 95;
 const STATE_ID: u8 = 0;
 const PREVIEW_ID: u8 = 1;
 const CHANNEL_ID: u8 = 2;
+const TIMER_ID: u8 = 3;
+const LEVEL_ID: u8 = 4;
+pub const TALK_BTN_ID: u8 = 5;
+pub const ACCEPT_BTN_ID: u8 = 6;
+/// Highest button id in use, relative to `btn_id_offset`.
+const LAST_BTN_ID: u8 = ACCEPT_BTN_ID;
+/// Number of bars shown by the input level meter at full level.
+const LEVEL_METER_BARS: usize = 10;
+/// Largest coordinate InSim buttons can be placed at.
+const MAX_BTN_COORD: u8 = 200;
+/// Shown in place of the preview once the audio pipeline has stopped.
+const PIPELINE_ERROR_TEXT: &str = "^1STT error, check the log";
+/// Shown in place of the level meter when the microphone delivers silence while recording.
+const NO_SIGNAL_TEXT: &str = "^1No mic signal";
+/// Meter level (0.0 - 1.0, -60 dB to 0 dB) below which the input counts as no signal.
+/// Only digital silence or a muted mic gets this low, even a quiet room is louder.
+const NO_SIGNAL_LEVEL: f32 = 0.02;
+/// How long the input has to stay silent while recording before `NO_SIGNAL_TEXT` is shown.
+const NO_SIGNAL_AFTER: Duration = Duration::from_millis(1500);
+/// Shown in place of the preview while the input device is gone.
+const DEVICE_LOST_TEXT: &str = "^1Microphone disconnected";
+/// Shown in the preview when a recording produced nothing usable.
+const NO_SPEECH_HINT_TEXT: &str = "^3Didn't catch that, try again";
+/// How long the no-speech hint stays before the preview goes back to the message.
+const NO_SPEECH_HINT_DURATION: Duration = Duration::from_secs(2);
+/// Shown in place of the state glyph while processing, one frame per tick.
+/// LFS fonts have no braille or other spinner characters, so these are plain ASCII.
+const SPINNER_FRAMES: [&str; 4] = ["|", "/", "-", "\\"];
+/// How often `UiContext::tick` should be called to refresh live UI elements.
+pub const TICK_INTERVAL: Duration = Duration::from_millis(250);
 
 #[derive(Debug, Clone, Copy)]
 pub enum UiState {
@@ -16,6 +48,10 @@ pub enum UiState {
     Recording,
     Processing,
     Stopped,
+    /// Turned off by the user, recording is ignored until it is turned back on.
+    Disabled,
+    /// The audio pipeline stopped, nothing can be recorded anymore.
+    Failed,
 }
 
 #[derive(Debug)]
@@ -23,72 +59,525 @@ pub enum UiEvent {
     UpdatePreview(String),
     UpdateState(UiState),
     UpdateChannel(ChatChannel),
+    UpdateTimer(u64),
+    UpdateLevel(f32),
+    /// Level meter replaced by `NO_SIGNAL_TEXT`.
+    ShowNoSignal,
+    /// State button while processing, with the given `SPINNER_FRAMES` frame.
+    UpdateSpinner(usize),
+    ShowControls,
     ClearPreview,
+    ClearRecordingWidgets,
     RemoveAllBtns,
 }
 
 pub struct UiContext {
     message_timeout: Option<Pin<Box<Sleep>>>,
+    /// Set while the no-speech hint is shown in place of the preview.
+    hint_timeout: Option<Pin<Box<Sleep>>>,
+    /// In-game value waiting to be applied once it is stable.
+    pending_in_game: Option<(bool, Pin<Box<Sleep>>)>,
     state: UiState,
     message: String,
+    /// Transcription time of the message in preview.
+    processing_ms: Option<u64>,
     update_queue: Vec<UiEvent>,
     chat_channels: Vec<ChatChannel>,
     active_channel: ChatChannel,
+    profanity_words: Vec<String>,
+    /// Normalized spoken phrase to the LFS command it triggers.
+    voice_commands: HashMap<String, String>,
+    /// Normalized spoken phrase to the index of the canned message it sends.
+    canned_phrases: HashMap<String, usize>,
+    recording_started: Option<Instant>,
+    timer_secs: Option<u64>,
+    level_bars: Option<usize>,
+    /// Since when the input level has been below `NO_SIGNAL_LEVEL` while recording.
+    silent_since: Option<Instant>,
+    no_signal_shown: bool,
+    spinner_frame: usize,
+    /// Stopped recordings and reloads the STT task has not answered yet.
+    pending_transcriptions: usize,
+    pipeline_failed: bool,
+    input_device_lost: bool,
+    enabled: bool,
+    /// Recording is blocked by the game state, see `block_recording_in`.
+    blocked: bool,
+    stats: SessionStats,
+    /// Candidates for the last transcription, the one in the message first.
+    alternatives: Vec<String>,
+    alternative_index: usize,
+    /// Last message sent to chat, kept after the preview is cleared so it can be repeated.
+    last_sent: Option<String>,
 }
 
 impl Default for UiContext {
     fn default() -> Self {
+        warn_layout_out_of_range();
+        warn_invalid_colors();
+        let chat_channels = CONFIG.chat_channels.clone();
+        let active_channel = restore_active_channel(&chat_channels);
         UiContext {
             state: UiState::Stopped,
             message: String::from(""),
+            processing_ms: None,
             message_timeout: None,
+            hint_timeout: None,
+            pending_in_game: None,
             update_queue: vec![],
-            active_channel: CONFIG.chat_channels[0].clone(),
-            chat_channels: CONFIG.chat_channels.clone(),
+            active_channel,
+            chat_channels,
+            profanity_words: load_profanity_words(),
+            voice_commands: load_voice_commands(),
+            canned_phrases: load_canned_phrases(),
+            recording_started: None,
+            timer_secs: None,
+            level_bars: None,
+            silent_since: None,
+            no_signal_shown: false,
+            spinner_frame: 0,
+            pending_transcriptions: 0,
+            pipeline_failed: false,
+            input_device_lost: false,
+            enabled: true,
+            blocked: false,
+            stats: SessionStats::default(),
+            alternatives: Vec::new(),
+            alternative_index: 0,
+            last_sent: None,
         }
     }
 }
 
 impl UiContext {
+    /// Resolves once the preview timeout fires, never resolves while no timeout is armed.
     pub async fn clear_message_timeout(&mut self) {
-        if let Some(t) = &mut self.message_timeout {
-            t.as_mut().await;
-            self.update_queue.push(UiEvent::ClearPreview);
-            self.message.clear();
-            self.message_timeout = None;
+        match &mut self.message_timeout {
+            Some(t) => {
+                t.as_mut().await;
+                self.update_queue.push(UiEvent::ClearPreview);
+                self.message.clear();
+                self.message_timeout = None;
+            },
+            None => std::future::pending().await,
+        }
+    }
+
+    /// Resolves once the no-speech hint expired and puts the preview back,
+    /// never resolves while no hint is shown.
+    pub async fn clear_hint_timeout(&mut self) {
+        match &mut self.hint_timeout {
+            Some(t) => {
+                t.as_mut().await;
+                self.hint_timeout = None;
+                // Error texts replaced the hint in the meantime, leave them up.
+                if matches!(self.state, UiState::Stopped) || self.pipeline_failed || self.input_device_lost {
+                    return;
+                }
+                if self.message.is_empty() {
+                    self.update_queue.push(UiEvent::ClearPreview);
+                } else {
+                    self.push_preview();
+                }
+            },
+            None => std::future::pending().await,
+        }
+    }
+
+    /// Refresh time based UI elements, call every `TICK_INTERVAL`.
+    pub fn tick(&mut self) {
+        if let (UiState::Recording, Some(started)) = (self.state, self.recording_started) {
+            let timeout = Duration::from_secs_f32(CONFIG.recording_timeout_secs);
+            let remaining = timeout.saturating_sub(started.elapsed());
+            let secs = remaining.as_secs() + u64::from(remaining.subsec_nanos() > 0);
+            if self.timer_secs != Some(secs) {
+                self.timer_secs = Some(secs);
+                self.update_queue.push(UiEvent::UpdateTimer(secs));
+            }
+        }
+
+        if let (UiState::Recording, Some(since)) = (self.state, self.silent_since)
+            && !self.no_signal_shown
+            && since.elapsed() >= NO_SIGNAL_AFTER
+        {
+            warn!("No signal from the microphone, check that it is not muted");
+            self.no_signal_shown = true;
+            self.update_queue.push(UiEvent::ShowNoSignal);
+        }
+
+        if let UiState::Processing = self.state {
+            self.spinner_frame = (self.spinner_frame + 1) % SPINNER_FRAMES.len();
+            self.update_queue.push(UiEvent::UpdateSpinner(self.spinner_frame));
+        } else {
+            // Start from the first frame next time processing begins.
+            self.spinner_frame = 0;
+        }
+    }
+
+    pub fn stats(&self) -> &SessionStats {
+        &self.stats
+    }
+
+    /// Drop pending updates and remove every button, used on shutdown.
+    pub fn remove_all_buttons(&mut self) {
+        self.update_queue.clear();
+        self.update_queue.push(UiEvent::RemoveAllBtns);
+    }
+
+    /// Resolves once an in-game change has been stable for `in_game_debounce_ms` and applies it,
+    /// never resolves while no change is pending.
+    pub async fn apply_in_game_change(&mut self) {
+        match &mut self.pending_in_game {
+            Some((is_in_game, timer)) => {
+                let is_in_game = *is_in_game;
+                timer.as_mut().await;
+                self.set_in_game(is_in_game);
+            },
+            None => std::future::pending().await,
+        }
+    }
+
+    /// Show or remove the UI for entering or leaving the game, without debouncing.
+    pub fn set_in_game(&mut self, is_in_game: bool) {
+        self.pending_in_game = None;
+        if is_in_game {
+            match self.state {
+                UiState::Stopped => {
+                    info!("Detected in-game state, starting STT.");
+                    self.state = self.resting_state();
+                    if self.pipeline_failed {
+                        self.update_queue.push(UiEvent::UpdatePreview(PIPELINE_ERROR_TEXT.into()));
+                    } else if self.input_device_lost {
+                        self.update_queue.push(UiEvent::UpdatePreview(DEVICE_LOST_TEXT.into()));
+                    } else if restore_message(&mut self.message, &mut self.message_timeout, Duration::from_secs(CONFIG.message_preview_timeout_secs)) {
+                        self.push_preview();
+                    }
+                    self.update_queue.push(UiEvent::UpdateState(self.state));
+                    self.update_queue.push(UiEvent::UpdateChannel(self.active_channel.clone()));
+                    if CONFIG.show_control_buttons {
+                        self.update_queue.push(UiEvent::ShowControls);
+                    }
+                },
+                _ => { /* No state change */ }
+            };
+        } else {
+            match self.state {
+                UiState::Stopped => { /* No state change */ }
+                _ => {
+                    info!("Detected not in-game state, stopping STT.");
+                    self.state = UiState::Stopped;
+                    self.recording_started = None;
+                    self.timer_secs = None;
+                    self.level_bars = None;
+                    self.silent_since = None;
+                    self.no_signal_shown = false;
+                    self.update_queue.push(UiEvent::RemoveAllBtns);
+                }
+            };
+        }
+    }
+
+    /// State to return to when nothing is being recorded or transcribed.
+    fn resting_state(&self) -> UiState {
+        if self.pipeline_failed {
+            UiState::Failed
+        } else if !self.enabled || self.blocked {
+            UiState::Disabled
+        } else {
+            UiState::Idle
+        }
+    }
+
+    /// The recording went to the STT task, which answers once for it.
+    fn recording_stopped(&mut self) {
+        self.pending_transcriptions += 1;
+        self.state = UiState::Processing;
+        self.update_queue.push(UiEvent::UpdateState(self.state));
+        self.stop_recording_widgets();
+    }
+
+    /// The STT task answered for a recording or reload. A recording started meanwhile keeps
+    /// going, otherwise the state rests once nothing is left in flight.
+    fn transcription_done(&mut self) {
+        self.pending_transcriptions = self.pending_transcriptions.saturating_sub(1);
+        if let UiState::Recording = self.state {
+            return;
+        }
+        self.stop_recording_widgets();
+        if let UiState::Processing = self.state && self.pending_transcriptions == 0 {
+            self.state = self.resting_state();
+            self.update_queue.push(UiEvent::UpdateState(self.state));
+        }
+    }
+
+    /// Discard the recording in progress, earlier recordings still being transcribed keep
+    /// the state at processing.
+    async fn cancel_recording(&mut self, audio_pipeline: &mut AudioPipeline) {
+        info!("Recording cancelled");
+        self.state = if self.pending_transcriptions > 0 { UiState::Processing } else { UiState::Idle };
+        self.update_queue.push(UiEvent::UpdateState(self.state));
+        self.stop_recording_widgets();
+        audio_pipeline.cancel_recording().await;
+    }
+
+    /// Show a change of `enabled` or `blocked`, a recording in progress is discarded.
+    async fn apply_resting_state(&mut self, audio_pipeline: &mut AudioPipeline) {
+        if let UiState::Recording = self.state {
+            self.cancel_recording(audio_pipeline).await;
+        }
+        // While processing, the state changes once the transcription is done.
+        if matches!(self.state, UiState::Idle | UiState::Disabled) {
+            self.state = self.resting_state();
+            self.update_queue.push(UiEvent::UpdateState(self.state));
+        }
+    }
+
+    /// Show the audio pipeline as broken, recording stays disabled from now on.
+    pub fn handle_pipeline_failure(&mut self) {
+        self.pipeline_failed = true;
+        self.pending_transcriptions = 0;
+        self.stop_recording_widgets();
+        if let UiState::Stopped = self.state {
+            return;
+        }
+
+        self.state = UiState::Failed;
+        self.update_queue.push(UiEvent::UpdateState(self.state));
+        self.message.clear();
+        self.message_timeout = None;
+        self.update_queue.push(UiEvent::UpdatePreview(PIPELINE_ERROR_TEXT.into()));
+    }
+
+    /// Recording is disabled while the input device is gone, the pipeline transcribes what was
+    /// recorded before it went away.
+    pub fn handle_input_device_event(&mut self, event: InputDeviceEvent) {
+        match event {
+            InputDeviceEvent::Lost => {
+                self.input_device_lost = true;
+                if let UiState::Recording = self.state {
+                    self.recording_stopped();
+                }
+                if !matches!(self.state, UiState::Stopped) {
+                    self.update_queue.push(UiEvent::UpdatePreview(DEVICE_LOST_TEXT.into()));
+                }
+            },
+            InputDeviceEvent::Restored => {
+                self.input_device_lost = false;
+                if matches!(self.state, UiState::Stopped) || self.pipeline_failed {
+                    return;
+                }
+                if self.message.is_empty() {
+                    self.update_queue.push(UiEvent::ClearPreview);
+                } else {
+                    self.push_preview();
+                }
+            },
+        }
+    }
+
+    fn set_active_channel(&mut self, index: usize) {
+        self.active_channel = self.chat_channels[index].clone();
+        self.update_queue.push(UiEvent::UpdateChannel(self.active_channel.clone()));
+        // Refresh the channel shown in the preview, unless something else is shown in its place.
+        let preview_shown = !self.message.is_empty() && self.hint_timeout.is_none()
+            && !self.pipeline_failed && !self.input_device_lost;
+        if CONFIG.show_channel_in_preview && preview_shown {
+            self.push_preview();
+        }
+        AppState {
+            active_channel_prefix: Some(self.active_channel.prefix.clone()),
+        }.save();
+    }
+
+    /// Show the message in preview, with the transcription time if `show_latency` is enabled
+    /// and the channel it goes to if `show_channel_in_preview` is enabled.
+    fn push_preview(&mut self) {
+        self.hint_timeout = None;
+        let colour = preview_colour(&self.active_channel);
+        let mut preview = match self.processing_ms {
+            Some(ms) if CONFIG.show_latency => format!("^{}{} ^8({:.1}s)", colour, self.message, ms as f32 / 1000.0),
+            _ => format!("^{}{}", colour, self.message),
+        };
+        if self.has_alternatives() {
+            preview.push_str(&format!(" ^8[{}/{}]", self.alternative_index + 1, self.alternatives.len()));
+        }
+        if CONFIG.show_channel_in_preview {
+            preview = format!("^8[{}^8] {}", self.active_channel.label(), preview);
+        }
+        self.update_queue.push(UiEvent::UpdatePreview(preview));
+    }
+
+    /// Send a message on the active channel, split over as many chat lines as needed.
+    /// Returns false if nothing could be sent.
+    async fn send_chat_message(&self, message: &str, insim: &InsimTask) -> bool {
+        if self.active_channel.target.as_deref() == Some("") {
+            warn!("No recipient for {} yet, set one with \"/o {} <name>\"", self.active_channel.display, CONFIG.insim_commands.message_target.first().map(String::as_str).unwrap_or("stt to"));
+            return false;
+        }
+        // Split message into chunks of MAX_MESSAGE_LEN and send each chunk as a separate Msx packet.
+        let prefix = self.active_channel.outgoing_prefix();
+        if prefix.len() >= MAX_MESSAGE_LEN {
+            warn!("Chat channel prefix \"{}\" is longer than a chat line, the message will be cut off by LFS", prefix);
+        }
+        let (max_len, max_bytes) = text::chunk_limits(&prefix, MAX_MESSAGE_LEN);
+        let mut chunks = text::split_message(message, max_len, max_bytes);
+        if chunks.len() > CONFIG.max_chat_messages {
+            warn!("Message is {} chat lines long, truncating to max_chat_messages = {}", chunks.len(), CONFIG.max_chat_messages);
+            chunks.truncate(CONFIG.max_chat_messages);
+            if let Some(last) = chunks.last_mut() {
+                let shortened = text::split_message(last, max_len.saturating_sub(3), max_bytes.saturating_sub(3)).into_iter().next().unwrap_or_default();
+                *last = format!("{}...", shortened);
+            }
+        }
+        let mut messages: Vec<String> = chunks
+            .into_iter()
+            .map(|chunk| format!("{} {}", prefix, chunk))
+            .rev()
+            .collect();
+
+        let mut first = true;
+        while let Some(part) = messages.pop() {
+            // Space out the lines so LFS flood protection doesn't kick us.
+            if !first {
+                tokio::time::sleep(Duration::from_millis(CONFIG.chat_send_interval_ms)).await;
+            }
+            first = false;
+            let msg = insim::insim::Msx{
+                reqi: insim::identifiers::RequestId::from(1),
+                msg: part.to_string(),
+            };
+            let _ = insim.send(insim::Packet::Msx(msg)).await;
+        }
+
+        true
+    }
+
+    /// Show `recording_announcement`, if set, where `recording_announcement_target` says.
+    async fn announce_recording(&self, insim: &InsimTask) {
+        let Some(announcement) = CONFIG.recording_announcement.as_deref() else { return; };
+        match CONFIG.recording_announcement_target {
+            AnnouncementTarget::Local => {
+                let msl = insim::insim::Msl{
+                    reqi: insim::identifiers::RequestId::from(1),
+                    msg: announcement.to_string(),
+                    ..Default::default()
+                };
+                let _ = insim.send(insim::Packet::Msl(msl)).await;
+            },
+            AnnouncementTarget::Chat => {
+                self.send_chat_message(announcement, insim).await;
+            },
+        }
+    }
+
+    async fn send_canned_message(&mut self, index: usize, insim: &InsimTask) {
+        let Some(canned) = CONFIG.canned_messages.get(index) else { return; };
+        info!("Sending canned message \"{}\"", canned.text);
+        let text = canned.text.trim().to_string();
+        if self.send_chat_message(&text, insim).await {
+            self.last_sent = Some(text);
+        }
+    }
+
+    /// Whether the message still ends with the last transcription, so it can be swapped out.
+    fn has_alternatives(&self) -> bool {
+        self.alternatives.len() > 1 && self.message.ends_with(&self.alternatives[self.alternative_index])
+    }
+
+    fn arm_message_timeout(&mut self) {
+        self.message_timeout = Some(Box::pin(
+            tokio::time::sleep(Duration::from_secs(CONFIG.message_preview_timeout_secs))
+        ));
+    }
+
+    /// Update the input level meter, `level` is in 0.0 - 1.0 range.
+    pub fn handle_input_level(&mut self, level: f32) {
+        if !matches!(self.state, UiState::Recording) {
+            return;
+        }
+
+        if level < NO_SIGNAL_LEVEL {
+            self.silent_since.get_or_insert_with(Instant::now);
+        } else {
+            self.silent_since = None;
+        }
+
+        let bars = level_to_bars(level);
+        // The meter comes back as soon as there is a signal again.
+        let signal_back = self.no_signal_shown && self.silent_since.is_none();
+        if self.level_bars != Some(bars) || signal_back {
+            self.no_signal_shown = false;
+            self.level_bars = Some(bars);
+            self.update_queue.push(UiEvent::UpdateLevel(level));
+        }
+    }
+
+    fn start_recording_widgets(&mut self) {
+        self.recording_started = Some(Instant::now());
+        self.timer_secs = None;
+        self.level_bars = None;
+        self.silent_since = None;
+        self.no_signal_shown = false;
+        self.tick();
+        self.handle_input_level(0.0);
+    }
+
+    fn stop_recording_widgets(&mut self) {
+        if self.recording_started.take().is_some() {
+            self.timer_secs = None;
+            self.level_bars = None;
+            self.silent_since = None;
+            self.no_signal_shown = false;
+            self.update_queue.push(UiEvent::ClearRecordingWidgets);
         }
     }
 
     pub async fn dispatch_ui_events(&mut self, insim: InsimTask) {
-        if !self.update_queue.is_empty() {
-            debug!("Dispatching {} UI events", self.update_queue.len());
+        if self.update_queue.is_empty() {
+            return;
         }
 
-        while let Some(event) = self.update_queue.pop() {
+        let queued = self.update_queue.len();
+        let events = coalesce_ui_events(std::mem::take(&mut self.update_queue));
+        debug!("Dispatching {} UI events ({} queued)", events.len(), queued);
+
+        for event in events {
             match event {
                 UiEvent::UpdatePreview(message) => {
                     let _ = insim.send(insim::Packet::Btn(get_message_preview_btn(message))).await;
                 },
                 UiEvent::ClearPreview => {
-                    let bfn = insim::insim::Bfn {
-                        subt: insim::insim::BfnType::DelBtn,
-                        reqi: insim::identifiers::RequestId::from(1),
-                        clickid: insim::identifiers::ClickId::from(CONFIG.btn_id_offset + PREVIEW_ID),
-                        clickmax: 0,
-                        ucid: insim::identifiers::ConnectionId::LOCAL,
-                        ..Default::default()
-                    };
-                    let _ = insim.send(insim::Packet::Bfn(bfn)).await;
+                    let _ = insim.send(insim::Packet::Bfn(get_del_btn(PREVIEW_ID))).await;
+                },
+                UiEvent::ClearRecordingWidgets => {
+                    let _ = insim.send(insim::Packet::Bfn(get_del_btn(TIMER_ID))).await;
+                    let _ = insim.send(insim::Packet::Bfn(get_del_btn(LEVEL_ID))).await;
+                },
+                UiEvent::ShowControls => {
+                    let _ = insim.send(insim::Packet::Btn(get_control_btn(TALK_BTN_ID, "Talk", 0))).await;
+                    let _ = insim.send(insim::Packet::Btn(get_control_btn(ACCEPT_BTN_ID, "Send", 1))).await;
+                },
+                UiEvent::UpdateLevel(level) => {
+                    let _ = insim.send(insim::Packet::Btn(get_level_btn(level))).await;
+                },
+                UiEvent::ShowNoSignal => {
+                    let _ = insim.send(insim::Packet::Btn(level_slot_btn(NO_SIGNAL_TEXT))).await;
+                },
+                UiEvent::UpdateTimer(secs) => {
+                    let _ = insim.send(insim::Packet::Btn(get_timer_btn(secs))).await;
                 },
                 UiEvent::UpdateState(state) => {
                     let _ = insim.send(insim::Packet::Btn(get_state_btn(state))).await;
                 },
+                UiEvent::UpdateSpinner(frame) => {
+                    let _ = insim.send(insim::Packet::Btn(get_spinner_btn(frame))).await;
+                },
                 UiEvent::RemoveAllBtns => {
                     let _ = insim.send(insim::Packet::Bfn(insim::insim::Bfn{
                         subt: insim::insim::BfnType::Clear,
                         reqi: insim::identifiers::RequestId::from(1),
                         clickid: insim::identifiers::ClickId::from(CONFIG.btn_id_offset),
-                        clickmax: CONFIG.btn_id_offset + 3,
+                        clickmax: CONFIG.btn_id_offset + LAST_BTN_ID,
                         ucid: insim::identifiers::ConnectionId::LOCAL,
                         ..Default::default()
                     })).await;
@@ -100,20 +589,63 @@ impl UiContext {
         }
     }
 
-    pub fn handle_stt_message(&mut self, msg: SttMessage) {
+    pub async fn handle_stt_message(&mut self, msg: SttMessage, insim: InsimTask) {
         match msg.msg_type {
-            SttMessageType::TranscriptionError => {
-                error!("{}", msg);
+            SttMessageType::TranscriptionError | SttMessageType::NoSpeech => {
+                self.transcription_done();
+                if let SttMessageType::TranscriptionError = msg.msg_type {
+                    error!("{}", msg);
+                } else {
+                    info!("{}", msg);
+                    if CONFIG.show_no_speech_hint && !matches!(self.state, UiState::Stopped) {
+                        // Shown in place of the preview, the message itself is kept and comes back after.
+                        self.update_queue.push(UiEvent::UpdatePreview(NO_SPEECH_HINT_TEXT.into()));
+                        self.hint_timeout = Some(Box::pin(tokio::time::sleep(NO_SPEECH_HINT_DURATION)));
+                    }
+                }
+            },
+            SttMessageType::ModelReloaded | SttMessageType::RecordingTooShort => {
+                info!("{}", msg);
+                self.transcription_done();
+            },
+            SttMessageType::RecordingTimeoutReached => {
+                info!("{}", msg);
+                if let UiState::Recording = self.state {
+                    self.recording_stopped();
+                }
             },
             SttMessageType::TranscriptionResult => {
                 info!("{}", msg);
-                self.message = msg.content;
-                self.state = UiState::Idle;
-                self.update_queue.push(UiEvent::UpdateState(self.state));
-                self.update_queue.push(UiEvent::UpdatePreview(self.message.clone()));
-                self.message_timeout = Some(Box::pin(
-                    tokio::time::sleep(std::time::Duration::from_secs(CONFIG.message_preview_timeout_secs))
-                ));
+                self.stats.record(&msg.content, msg.audio_ms, msg.processing_ms);
+                play_feedback_tone(CONFIG.feedback_tones.done_frequency_hz);
+                self.transcription_done();
+
+                if let Some(command) = self.voice_commands.get(&text::normalize_phrase(&msg.content)) {
+                    info!("Voice command \"{}\" matched, sending {}", msg.content, command);
+                    let mst = insim::insim::Mst{
+                        reqi: insim::identifiers::RequestId::from(1),
+                        msg: command.clone(),
+                    };
+                    let _ = insim.send(insim::Packet::Mst(mst)).await;
+                    return;
+                }
+
+                if let Some(&index) = self.canned_phrases.get(&text::normalize_phrase(&msg.content)) {
+                    self.send_canned_message(index, &insim).await;
+                    return;
+                }
+
+                self.alternatives = std::iter::once(msg.content.clone()).chain(msg.alternatives).collect();
+                self.alternative_index = 0;
+                if CONFIG.append_transcriptions && !self.message.is_empty() {
+                    self.message.push(' ');
+                    self.message.push_str(&msg.content);
+                } else {
+                    self.message = msg.content;
+                }
+                self.processing_ms = Some(msg.processing_ms);
+                self.push_preview();
+                self.arm_message_timeout();
             },
         };
     }
@@ -121,82 +653,167 @@ impl UiContext {
     pub async fn handle_insim_event(&mut self, event: InsimEvent, insim: InsimTask, audio_pipeline: &mut AudioPipeline) {
         match event {
             InsimEvent::IsInGame(is_in_game) => {
-                if is_in_game {
-                    match self.state {
-                        UiState::Stopped => {
-                            info!("Detected in-game state, starting STT.");
-                            self.state = UiState::Idle;
-                            if !self.message.is_empty() {
-                                self.update_queue.push(UiEvent::UpdatePreview(self.message.clone()));
-                            }
-                            self.update_queue.push(UiEvent::UpdateState(self.state));
-                            self.update_queue.push(UiEvent::UpdateChannel(self.active_channel.clone()));
-                        },
-                        _ => { /* No state change */ }
-                    };
-                } else {
-                    match self.state {
-                        UiState::Stopped => { /* No state change */ }
-                        _ => {
-                            info!("Detected not in-game state, stopping STT.");
-                            self.state = UiState::Stopped;
-                            self.update_queue.push(UiEvent::RemoveAllBtns);
-                        }
-                    };
+                // The flag flickers during loading screens, only act once it is stable.
+                if CONFIG.in_game_debounce_ms == 0 {
+                    self.set_in_game(is_in_game);
+                    return;
+                }
+
+                let current = !matches!(self.state, UiState::Stopped);
+                if is_in_game == current {
+                    self.pending_in_game = None;
+                } else if !matches!(&self.pending_in_game, Some((pending, _)) if *pending == is_in_game) {
+                    self.pending_in_game = Some((
+                        is_in_game,
+                        Box::pin(tokio::time::sleep(Duration::from_millis(CONFIG.in_game_debounce_ms))),
+                    ));
                 }
             },
             InsimEvent::ToggleRecording => {
                 match self.state {
-                    UiState::Processing => {},
                     UiState::Stopped => {},
-                    UiState::Idle => {
+                    UiState::Failed => {},
+                    UiState::Disabled => {},
+                    UiState::Idle | UiState::Processing if self.input_device_lost => {
+                        warn!("Can't record, the audio input device is not available");
+                    },
+                    // Earlier recordings keep going through the transcription queue meanwhile.
+                    UiState::Idle | UiState::Processing => {
                         info!("Started recording...");
                         self.state = UiState::Recording;
                         self.update_queue.push(UiEvent::UpdateState(self.state));
+                        self.start_recording_widgets();
+                        play_feedback_tone(CONFIG.feedback_tones.start_frequency_hz);
                         audio_pipeline.start_recording().await;
+                        self.announce_recording(&insim).await;
                     },
                     UiState::Recording => {
                         info!("Stopped recording...");
-                        self.state = UiState::Processing;
-                        self.update_queue.push(UiEvent::UpdateState(self.state));
+                        self.recording_stopped();
                         audio_pipeline.stop_recording_and_transcribe().await;
                     },
                 };
             },
+            InsimEvent::SetEnabled(enabled) => {
+                if self.enabled == enabled { return; }
+
+                self.enabled = enabled;
+                info!("STT {}", if enabled { "enabled" } else { "disabled" });
+                self.apply_resting_state(audio_pipeline).await;
+            },
+            InsimEvent::IsBlocked(blocked) => {
+                if self.blocked == blocked { return; }
+
+                self.blocked = blocked;
+                info!("Recording {} by the game state", if blocked { "blocked" } else { "unblocked" });
+                self.apply_resting_state(audio_pipeline).await;
+            },
+            InsimEvent::CancelRecording => {
+                if let UiState::Recording = self.state {
+                    self.cancel_recording(audio_pipeline).await;
+                }
+            },
+            InsimEvent::ClearMessage => {
+                if self.message.is_empty() { return; }
+
+                info!("Message cleared");
+                self.update_queue.push(UiEvent::ClearPreview);
+                self.message.clear();
+                self.message_timeout = None;
+                self.hint_timeout = None;
+            },
             InsimEvent::AcceptMessage => {
                 if self.message.is_empty() { return; }
 
                 if let UiState::Idle = self.state {
-                    // Split message into chunks of MAX_MESSAGE_LEN and send each chunk as a separate Msx packet.
-                    let mut messages: Vec<String> = self.message.chars()
-                        .collect::<Vec<_>>()
-                        .chunks(MAX_MESSAGE_LEN - self.active_channel.prefix.len())
-                        .map(|chunk| {
-                            let mut msg = format!("{} ", self.active_channel.prefix);
-                            msg.push_str(chunk.iter().collect::<String>().as_str());
-                            msg
-                        })
-                        .rev()
-                        .collect();
-
-                    while let Some(part) = messages.pop() {
-                        let msg = insim::insim::Msx{
-                            reqi: insim::identifiers::RequestId::from(1),
-                            msg: part.to_string(),
-                        };
-                        let _ = insim.send(insim::Packet::Msx(msg)).await;
-                    }
+                    let message = if CONFIG.profanity_filter {
+                        text::censor(&self.message, &self.profanity_words)
+                    } else {
+                        self.message.clone()
+                    };
+
+                    if !self.send_chat_message(&message, &insim).await { return; }
+                    self.last_sent = Some(message);
 
                     self.update_queue.push(UiEvent::ClearPreview);
                     self.message.clear();
                     self.message_timeout = None;
                 };
             },
+            InsimEvent::ReloadModel => {
+                // Busy until the STT task reports back, a recording in flight is transcribed first.
+                if let UiState::Idle = self.state {
+                    info!("Reloading model...");
+                    self.pending_transcriptions += 1;
+                    self.state = UiState::Processing;
+                    self.update_queue.push(UiEvent::UpdateState(self.state));
+                    audio_pipeline.reload_model(ARGS.model_path.as_deref()).await;
+                }
+            },
+            InsimEvent::SendCanned(index) => {
+                if matches!(self.state, UiState::Stopped) { return; }
+                self.send_canned_message(index, &insim).await;
+            },
+            InsimEvent::RepeatLast => {
+                if matches!(self.state, UiState::Stopped) { return; }
+                let Some(message) = self.last_sent.clone() else { return; };
+
+                info!("Repeating last message");
+                self.send_chat_message(&message, &insim).await;
+            },
+            InsimEvent::DropLastWord => {
+                if self.message.is_empty() { return; }
+
+                let trimmed = self.message.trim_end();
+                let kept_len = trimmed.rfind(char::is_whitespace).unwrap_or(0);
+                self.message.truncate(kept_len);
+                let kept = self.message.trim_end().len();
+                self.message.truncate(kept);
+
+                if self.message.is_empty() {
+                    self.update_queue.push(UiEvent::ClearPreview);
+                    self.message_timeout = None;
+                } else {
+                    self.push_preview();
+                    self.arm_message_timeout();
+                }
+            },
+            InsimEvent::KeepMessage => {
+                if self.message.is_empty() { return; }
+
+                // Toggles between keeping the preview until it is sent and the normal timeout.
+                if self.message_timeout.take().is_some() {
+                    info!("Keeping message in preview until it is sent");
+                } else {
+                    info!("Message preview timeout re-armed");
+                    self.arm_message_timeout();
+                }
+            },
+            InsimEvent::SetMessageTarget(target) => {
+                let Some(index) = self.chat_channels.iter().position(|c| c.target.is_some()) else {
+                    warn!("No private chat channel configured (a channel with a target), can't send to {}", target);
+                    return;
+                };
+                info!("Sending messages to {}", target);
+                self.chat_channels[index].target = Some(target);
+                self.set_active_channel(index);
+            },
+            InsimEvent::NextAlternative => {
+                if !self.has_alternatives() { return; }
+
+                let current = &self.alternatives[self.alternative_index];
+                self.message.truncate(self.message.len() - current.len());
+                self.alternative_index = (self.alternative_index + 1) % self.alternatives.len();
+                self.message.push_str(&self.alternatives[self.alternative_index]);
+                self.push_preview();
+                if self.message_timeout.is_some() {
+                    self.arm_message_timeout();
+                }
+            },
             InsimEvent::NextChannel => {
                 let current_index = self.chat_channels.iter().position(|c| c == &self.active_channel).unwrap_or(0);
                 let next_index = (current_index + 1) % self.chat_channels.len();
-                self.active_channel = self.chat_channels[next_index].clone();
-                self.update_queue.push(UiEvent::UpdateChannel(self.active_channel.clone()));
+                self.set_active_channel(next_index);
             },
             InsimEvent::PeviousChannel => {
                 let current_index = self.chat_channels.iter().position(|c| c == &self.active_channel).unwrap_or(0);
@@ -205,27 +822,187 @@ impl UiContext {
                 } else {
                     current_index - 1
                 };
-                self.active_channel = self.chat_channels[previous_index].clone();
-                self.update_queue.push(UiEvent::UpdateChannel(self.active_channel.clone()));
+                self.set_active_channel(previous_index);
+            },
+        }
+    }
+}
+
+/// Drop button updates that are overwritten by a newer update of the same button.
+/// Clear and remove events are always kept, and the order of the remaining events is preserved.
+/// Message to show again when coming back into the game. It gets the full preview time again,
+/// unless it is kept, and one whose time ran out while away is dropped.
+/// Returns whether there is a message to show.
+fn restore_message(message: &mut String, timeout: &mut Option<Pin<Box<Sleep>>>, preview_timeout: Duration) -> bool {
+    if timeout.as_ref().is_some_and(|t| t.deadline() <= Instant::now()) {
+        message.clear();
+        *timeout = None;
+    }
+    if message.is_empty() {
+        return false;
+    }
+
+    if timeout.is_some() {
+        *timeout = Some(Box::pin(tokio::time::sleep(preview_timeout)));
+    }
+    true
+}
+
+fn coalesce_ui_events(events: Vec<UiEvent>) -> Vec<UiEvent> {
+    let mut seen = Vec::new();
+    let mut kept = Vec::with_capacity(events.len());
+    for event in events.into_iter().rev() {
+        let is_update = matches!(
+            event,
+            UiEvent::UpdatePreview(_)
+                | UiEvent::UpdateState(_)
+                | UiEvent::UpdateChannel(_)
+                | UiEvent::UpdateTimer(_)
+                | UiEvent::UpdateLevel(_)
+                | UiEvent::UpdateSpinner(_)
+        );
+        if is_update {
+            let kind = std::mem::discriminant(&event);
+            if seen.contains(&kind) {
+                continue;
+            }
+            seen.push(kind);
+        }
+        kept.push(event);
+    }
+    kept.reverse();
+    kept
+}
+
+fn play_feedback_tone(frequency_hz: f32) {
+    let tones = &CONFIG.feedback_tones;
+    if tones.enabled {
+        feedback::play_tone(frequency_hz, tones.duration_ms, tones.volume);
+    }
+}
+
+/// Channel selected in the previous session, if it is still configured.
+fn restore_active_channel(chat_channels: &[ChatChannel]) -> ChatChannel {
+    if let Some(prefix) = AppState::load().active_channel_prefix {
+        match chat_channels.iter().find(|c| c.prefix == prefix) {
+            Some(channel) => {
+                info!("Restored last active channel {}", channel.display);
+                return channel.clone();
             },
+            None => info!("Last active channel \"{}\" is no longer configured, using the first channel", prefix),
+        }
+    }
+    chat_channels[0].clone()
+}
+
+/// Words from `profanity_words` and one word or phrase per line from `profanity_words_path`.
+fn load_profanity_words() -> Vec<String> {
+    let mut words = CONFIG.profanity_words.clone();
+    if let Some(path) = &CONFIG.profanity_words_path {
+        match std::fs::read_to_string(path) {
+            Ok(contents) => words.extend(
+                contents.lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                    .map(String::from)
+            ),
+            Err(e) => warn!("Failed to read profanity words from {}: {}", path, e),
+        }
+    }
+    words
+}
+
+fn load_voice_commands() -> HashMap<String, String> {
+    CONFIG.voice_commands.iter()
+        .map(|(phrase, command)| (text::normalize_phrase(phrase), command.trim().to_string()))
+        .collect()
+}
+
+fn load_canned_phrases() -> HashMap<String, usize> {
+    CONFIG.canned_messages.iter()
+        .enumerate()
+        .flat_map(|(index, canned)| canned.phrases.iter().map(move |phrase| (text::normalize_phrase(phrase), index)))
+        .collect()
+}
+
+fn warn_layout_out_of_range() {
+    let layout = [
+        ("ui_scale", CONFIG.ui_scale),
+        ("ui_offset_top", CONFIG.ui_offset_top),
+        ("ui_offset_left", CONFIG.ui_offset_left),
+    ];
+    for (name, value) in layout {
+        if value > MAX_BTN_COORD {
+            warn!("{} = {} is out of range, clamping to {}", name, value, MAX_BTN_COORD);
         }
     }
 }
 
+fn warn_invalid_colors() {
+    let colors = [
+        ("idle", CONFIG.ui_colors.idle),
+        ("recording", CONFIG.ui_colors.recording),
+        ("processing", CONFIG.ui_colors.processing),
+        ("disabled", CONFIG.ui_colors.disabled),
+        ("preview", CONFIG.ui_colors.preview),
+    ];
+    let optional = [
+        ("preview_public", CONFIG.ui_colors.preview_public),
+        ("preview_private", CONFIG.ui_colors.preview_private),
+    ];
+    let set = optional.into_iter().filter_map(|(name, value)| Some((name, value?)));
+    for (name, value) in colors.into_iter().chain(set) {
+        if value > 9 {
+            warn!("ui_colors.{} = {} is not a valid colour code (0-9), using the default", name, value);
+        }
+    }
+}
+
+/// LFS colour codes are `^0` to `^9`.
+fn colour_code(value: u8, default: u8) -> u8 {
+    if value <= 9 { value } else { default }
+}
+
+/// Preview colour for messages going to `channel`, so a public broadcast can be told apart
+/// from a private message at a glance.
+fn preview_colour(channel: &ChatChannel) -> u8 {
+    let colors = &CONFIG.ui_colors;
+    let configured = if channel.target.is_some() { colors.preview_private } else { colors.preview_public };
+    colour_code(configured.unwrap_or(colors.preview), UiColors::default().preview)
+}
+
+/// Sum of button coordinates, clamped to the InSim button area.
+fn btn_coord(values: &[u8]) -> u8 {
+    let sum: u16 = values.iter().map(|&v| v as u16).sum();
+    sum.min(MAX_BTN_COORD as u16) as u8
+}
+
 fn get_state_btn(state: UiState) -> insim::insim::Btn {
+    let defaults = UiColors::default();
+    let colors = &CONFIG.ui_colors;
     let text = match state {
-        UiState::Idle => "^2•",
-        UiState::Recording => "^1•",
-        UiState::Processing => "^3•",
-        UiState::Stopped => "",
+        UiState::Idle => format!("^{}{}", colour_code(colors.idle, defaults.idle), colors.glyph),
+        UiState::Recording => format!("^{}{}", colour_code(colors.recording, defaults.recording), colors.glyph),
+        UiState::Processing => format!("^{}{}", colour_code(colors.processing, defaults.processing), colors.glyph),
+        UiState::Disabled => format!("^{}×", colour_code(colors.disabled, defaults.disabled)),
+        UiState::Failed => String::from("^1X"),
+        UiState::Stopped => String::new(),
     };
+    state_btn(text)
+}
 
+fn get_spinner_btn(frame: usize) -> insim::insim::Btn {
+    let processing = colour_code(CONFIG.ui_colors.processing, UiColors::default().processing);
+    state_btn(format!("^{}{}", processing, SPINNER_FRAMES[frame % SPINNER_FRAMES.len()]))
+}
+
+fn state_btn(text: String) -> insim::insim::Btn {
     insim::insim::Btn{
-        text: insim::core::string::escaping::escape(text).to_string(),
-        t: CONFIG.ui_offset_top,
-        w: CONFIG.ui_scale,
-        h: CONFIG.ui_scale,
-        l: CONFIG.ui_offset_left,
+        text: insim::core::string::escaping::escape(text.as_str()).to_string(),
+        t: btn_coord(&[CONFIG.ui_offset_top]),
+        w: btn_coord(&[CONFIG.ui_scale]),
+        h: btn_coord(&[CONFIG.ui_scale]),
+        l: btn_coord(&[CONFIG.ui_offset_left]),
         reqi: insim::identifiers::RequestId::from(1),
         ucid: insim::identifiers::ConnectionId::LOCAL,
         clickid: insim::identifiers::ClickId::from(CONFIG.btn_id_offset + STATE_ID),
@@ -237,22 +1014,34 @@ fn get_state_btn(state: UiState) -> insim::insim::Btn {
     }
 }
 
-/// depending on charaters used, width may vary
-/// todo: this is not too accurate. Do we have to look at specific chars?
+/// Approximate glyph widths of the LFS button font, in button width units.
+fn char_width(c: char) -> f32 {
+    match c {
+        'i' | 'j' | 'l' | 'I' | '!' | '|' | '\'' | '.' | ',' | ':' | ';' => 0.35,
+        'f' | 't' | 'r' | ' ' | '-' | '"' | '(' | ')' | '[' | ']' => 0.5,
+        'm' | 'w' => 1.1,
+        'M' | 'W' => 1.25,
+        c if c.is_ascii_uppercase() => 0.9,
+        _ => 0.75,
+    }
+}
+
+/// Width of a button that fits the visible text, colour codes are not counted.
 fn msg_to_btn_width(message: String) -> u8 {
-    let len = insim::core::string::colours::strip(message.as_str()).len();
-    let width = (len as f32 * 0.75).ceil() as u8 + 3;
-    width.clamp(1, 200)
+    let text = insim::core::string::colours::strip(message.as_str());
+    let width: f32 = text.chars().map(char_width).sum();
+    (width.ceil() + 3.0).clamp(1.0, MAX_BTN_COORD as f32) as u8
 }
 
+/// `message` brings its own colour, see `push_preview`.
 fn get_message_preview_btn(message: String) -> insim::insim::Btn {
-    let text = insim::core::string::escaping::escape(format!("^3{}", message).as_str()).to_string();
+    let text = insim::core::string::escaping::escape(message.as_str()).to_string();
     insim::insim::Btn{
         text,
-        t: CONFIG.ui_offset_top,
+        t: btn_coord(&[CONFIG.ui_offset_top]),
         w: msg_to_btn_width(message.clone()),
-        h: CONFIG.ui_scale,
-        l: CONFIG.ui_offset_left + CONFIG.ui_scale, // next to state
+        h: btn_coord(&[CONFIG.ui_scale]),
+        l: btn_coord(&[CONFIG.ui_offset_left, CONFIG.ui_scale]), // next to state
         reqi: insim::identifiers::RequestId::from(1),
         ucid: insim::identifiers::ConnectionId::LOCAL,
         clickid: insim::identifiers::ClickId::from(CONFIG.btn_id_offset + PREVIEW_ID),
@@ -264,15 +1053,100 @@ fn get_message_preview_btn(message: String) -> insim::insim::Btn {
     }
 }
 
+fn get_timer_btn(secs: u64) -> insim::insim::Btn {
+    insim::insim::Btn{
+        text: format!("^7{}s", secs),
+        t: CONFIG.ui_offset_top.saturating_sub(CONFIG.ui_scale).min(MAX_BTN_COORD),
+        l: btn_coord(&[CONFIG.ui_offset_left]),
+        h: btn_coord(&[CONFIG.ui_scale]),
+        w: btn_coord(&[CONFIG.ui_scale, CONFIG.ui_scale]),
+        reqi: insim::identifiers::RequestId::from(1),
+        ucid: insim::identifiers::ConnectionId::LOCAL,
+        clickid: insim::identifiers::ClickId::from(CONFIG.btn_id_offset + TIMER_ID),
+        bstyle: insim::insim::BtnStyle{
+            colour: insim::insim::BtnStyleColour::NotEditable,
+            flags: insim::insim::BtnStyleFlags::LIGHT,
+        },
+        ..Default::default()
+    }
+}
+
+fn level_to_bars(level: f32) -> usize {
+    (level.clamp(0.0, 1.0) * LEVEL_METER_BARS as f32).round() as usize
+}
+
+/// LFS fonts have no block characters, so the meter is drawn with `|`.
+fn get_level_btn(level: f32) -> insim::insim::Btn {
+    let bars = level_to_bars(level);
+    level_slot_btn(&format!("^2{}^8{}", "|".repeat(bars), "|".repeat(LEVEL_METER_BARS - bars)))
+}
+
+/// Button in the place of the level meter, sized to its text.
+fn level_slot_btn(text: &str) -> insim::insim::Btn {
+    insim::insim::Btn{
+        text: insim::core::string::escaping::escape(text).to_string(),
+        t: CONFIG.ui_offset_top.saturating_sub(CONFIG.ui_scale).min(MAX_BTN_COORD),
+        l: btn_coord(&[CONFIG.ui_offset_left, CONFIG.ui_scale, CONFIG.ui_scale]),
+        h: btn_coord(&[CONFIG.ui_scale]),
+        w: msg_to_btn_width(text.to_string()),
+        reqi: insim::identifiers::RequestId::from(1),
+        ucid: insim::identifiers::ConnectionId::LOCAL,
+        clickid: insim::identifiers::ClickId::from(CONFIG.btn_id_offset + LEVEL_ID),
+        bstyle: insim::insim::BtnStyle{
+            colour: insim::insim::BtnStyleColour::NotEditable,
+            flags: insim::insim::BtnStyleFlags::LIGHT | insim::insim::BtnStyleFlags::LEFT,
+        },
+        ..Default::default()
+    }
+}
+
+/// Clickable button in the row below the channel, `column` counts from the left.
+fn get_control_btn(id: u8, text: &str, column: u8) -> insim::insim::Btn {
+    let w = btn_coord(&[CONFIG.ui_scale, CONFIG.ui_scale, CONFIG.ui_scale]);
+
+    insim::insim::Btn{
+        text: text.to_string(),
+        t: btn_coord(&[CONFIG.ui_offset_top, CONFIG.ui_scale, CONFIG.ui_scale]),
+        l: btn_coord(&[CONFIG.ui_offset_left, w.saturating_mul(column)]),
+        h: btn_coord(&[CONFIG.ui_scale]),
+        w,
+        reqi: insim::identifiers::RequestId::from(1),
+        ucid: insim::identifiers::ConnectionId::LOCAL,
+        clickid: insim::identifiers::ClickId::from(CONFIG.btn_id_offset + id),
+        bstyle: insim::insim::BtnStyle{
+            colour: insim::insim::BtnStyleColour::NotEditable,
+            flags: insim::insim::BtnStyleFlags::DARK | insim::insim::BtnStyleFlags::CLICK,
+        },
+        ..Default::default()
+    }
+}
+
+fn get_del_btn(id: u8) -> insim::insim::Bfn {
+    insim::insim::Bfn {
+        subt: insim::insim::BfnType::DelBtn,
+        reqi: insim::identifiers::RequestId::from(1),
+        clickid: insim::identifiers::ClickId::from(CONFIG.btn_id_offset + id),
+        clickmax: 0,
+        ucid: insim::identifiers::ConnectionId::LOCAL,
+        ..Default::default()
+    }
+}
+
 fn get_channel_btn(channel: ChatChannel) -> insim::insim::Btn {
-    let text = insim::core::string::escaping::escape(channel.display.as_str()).to_string();
+    // Make it obvious that messages are sent in English, not the spoken language.
+    let display = if CONFIG.translate {
+        format!("{} ^8(EN)", channel.label())
+    } else {
+        channel.label()
+    };
+    let text = insim::core::string::escaping::escape(display.as_str()).to_string();
 
     insim::insim::Btn{
         text,
-        t: CONFIG.ui_offset_top + CONFIG.ui_scale,
-        l: CONFIG.ui_offset_left,
-        h: CONFIG.ui_scale,
-        w: msg_to_btn_width(channel.display.to_string()),
+        t: btn_coord(&[CONFIG.ui_offset_top, CONFIG.ui_scale]),
+        l: btn_coord(&[CONFIG.ui_offset_left]),
+        h: btn_coord(&[CONFIG.ui_scale]),
+        w: msg_to_btn_width(display),
         reqi: insim::identifiers::RequestId::from(1),
         ucid: insim::identifiers::ConnectionId::LOCAL,
         clickid: insim::identifiers::ClickId::from(CONFIG.btn_id_offset + CHANNEL_ID),
@@ -284,3 +1158,61 @@ fn get_channel_btn(channel: ChatChannel) -> insim::insim::Btn {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn btn_width_follows_glyph_widths() {
+        assert_eq!(msg_to_btn_width("iiiiiiiiii".into()), 7);
+        assert_eq!(msg_to_btn_width("MMMMMMMMMM".into()), 16);
+        assert_eq!(msg_to_btn_width("Hello world".into()), 11);
+    }
+
+    #[test]
+    fn btn_width_ignores_colour_codes() {
+        assert_eq!(msg_to_btn_width("^3iii".into()), msg_to_btn_width("iii".into()));
+        assert_eq!(msg_to_btn_width("^1Box ^7box".into()), msg_to_btn_width("Box box".into()));
+    }
+
+    #[test]
+    fn btn_width_is_clamped() {
+        assert_eq!(msg_to_btn_width(String::new()), 3);
+        assert_eq!(msg_to_btn_width("W".repeat(500)), MAX_BTN_COORD);
+    }
+
+    fn timeout_in(duration: Duration) -> Option<Pin<Box<Sleep>>> {
+        Some(Box::pin(tokio::time::sleep(duration)))
+    }
+
+    #[tokio::test]
+    async fn restored_message_gets_the_full_preview_time_again() {
+        let mut message = String::from("box box");
+        let mut timeout = timeout_in(Duration::from_secs(1));
+        let before = timeout.as_ref().unwrap().deadline();
+
+        assert!(restore_message(&mut message, &mut timeout, Duration::from_secs(10)));
+        assert_eq!(message, "box box");
+        assert!(timeout.as_ref().unwrap().deadline() >= before + Duration::from_secs(9));
+    }
+
+    #[tokio::test]
+    async fn restored_message_stays_kept() {
+        let mut message = String::from("box box");
+        let mut timeout = None;
+
+        assert!(restore_message(&mut message, &mut timeout, Duration::from_secs(10)));
+        assert!(timeout.is_none());
+    }
+
+    #[tokio::test]
+    async fn message_expired_while_away_is_dropped() {
+        let mut message = String::from("box box");
+        let mut timeout = timeout_in(Duration::ZERO);
+
+        assert!(!restore_message(&mut message, &mut timeout, Duration::from_secs(10)));
+        assert!(message.is_empty());
+        assert!(timeout.is_none());
+    }
+}